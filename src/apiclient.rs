@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use std::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::config::get_config;
+
+const USER_AGENT: &str = concat!("czskm-pbbot/", env!("CARGO_PKG_VERSION"));
+
+// First retry delay, doubled on every following attempt
+const BACKOFF_BASE: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+// Don't let a misbehaving API stall the bot for longer than this
+const RETRY_AFTER_MAX: Duration = Duration::from_secs(300);
+
+// speedrun.com answers 420 when it wants clients to slow down
+const ENHANCE_YOUR_CALM: u16 = 420;
+
+// HTTP client shared by all speedrun.com and Twitch requests, so connections
+// are pooled and the rate limit applies across both APIs
+pub struct ApiClient {
+    http: reqwest::Client,
+    limiter: Mutex<TokenBucket>,
+    max_retries: u32,
+}
+
+static API_CLIENT: Lazy<ApiClient> = Lazy::new(|| {
+    let config = &get_config().api;
+    let http = reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(config.timeout())
+        .connect_timeout(Duration::from_secs(10))
+        .build()
+        .expect("Failed to build HTTP client");
+    ApiClient {
+        http,
+        limiter: Mutex::new(TokenBucket::new(
+            config.burst,
+            f64::from(config.requests_per_minute) / 60.0,
+        )),
+        max_retries: config.max_retries,
+    }
+});
+
+pub fn api_client() -> &'static ApiClient {
+    &API_CLIENT
+}
+
+impl ApiClient {
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.http.request(method, url)
+    }
+
+    // Send a request once the rate limit allows it, retrying rate limited
+    // responses, server errors and timeouts with exponential backoff
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response> {
+        let mut attempt: u32 = 0;
+        loop {
+            let attempt_request = request
+                .try_clone()
+                .ok_or_else(|| anyhow!("Request can't be retried"))?;
+            self.wait().await;
+
+            let delay = match attempt_request.send().await {
+                Ok(response) if !is_retryable(response.status()) => return Ok(response),
+                Ok(response) => {
+                    if attempt >= self.max_retries {
+                        return Ok(response.error_for_status()?);
+                    }
+                    let delay = retry_after(response.headers(), Utc::now())
+                        .unwrap_or_else(|| backoff(attempt));
+                    log::warn!(
+                        "{} answered {}, retrying in {:?}",
+                        response.url(),
+                        response.status(),
+                        delay
+                    );
+                    println!(
+                        "[WARN] {} answered {}, retrying in {:?}",
+                        response.url(),
+                        response.status(),
+                        delay
+                    );
+                    delay
+                }
+                Err(why)
+                    if attempt < self.max_retries && (why.is_timeout() || why.is_connect()) =>
+                {
+                    let delay = backoff(attempt);
+                    log::warn!("Request failed, retrying in {:?}: {:?}", delay, why);
+                    println!("[WARN] Request failed, retrying in {:?}", delay);
+                    delay
+                }
+                Err(why) => return Err(why.into()),
+            };
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    // Wait for a token from the shared rate limiter
+    async fn wait(&self) {
+        let delay = self
+            .limiter
+            .lock()
+            .expect("Rate limiter lock poisoned")
+            .take(Instant::now());
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status.as_u16() == ENHANCE_YOUR_CALM
+        || status.is_server_error()
+}
+
+fn backoff(attempt: u32) -> Duration {
+    BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(BACKOFF_MAX)
+}
+
+// Delay requested by the server, either in seconds or as an HTTP date
+fn retry_after(headers: &HeaderMap, now: DateTime<Utc>) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => DateTime::parse_from_rfc2822(value)
+            .ok()?
+            .with_timezone(&Utc)
+            .signed_duration_since(now)
+            .to_std()
+            .unwrap_or(Duration::ZERO),
+    };
+    Some(delay.min(RETRY_AFTER_MAX))
+}
+
+// Allows bursts of up to `capacity` requests, refilling at a steady rate
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: u32, per_second: f64) -> Self {
+        TokenBucket {
+            capacity: f64::from(capacity),
+            tokens: f64::from(capacity),
+            per_second,
+            updated_at: Instant::now(),
+        }
+    }
+
+    // Take a token and return how long to wait until it's actually available.
+    // Tokens can go negative, which queues concurrent callers behind each other.
+    fn take(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.capacity);
+        self.updated_at = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn bucket_allows_burst_then_spaces_requests() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, 1.0);
+        assert_eq!(bucket.take(start), Duration::ZERO);
+        assert_eq!(bucket.take(start), Duration::ZERO);
+        assert_eq!(bucket.take(start), Duration::from_secs(1));
+        assert_eq!(bucket.take(start), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1, 0.5);
+        assert_eq!(bucket.take(start), Duration::ZERO);
+        assert_eq!(bucket.take(start + Duration::from_secs(2)), Duration::ZERO);
+        // Refilling never goes above the burst size
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.take(later), Duration::ZERO);
+        assert_eq!(bucket.take(later), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        assert_eq!(backoff(0), Duration::from_secs(1));
+        assert_eq!(backoff(3), Duration::from_secs(8));
+        assert_eq!(backoff(10), BACKOFF_MAX);
+        assert_eq!(backoff(u32::MAX), BACKOFF_MAX);
+    }
+
+    #[test]
+    fn retry_after_parses_seconds_and_dates() {
+        let now = DateTime::parse_from_rfc3339("2015-10-21T07:28:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers, now), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(7)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:30 GMT"),
+        );
+        assert_eq!(retry_after(&headers, now), Some(Duration::from_secs(30)));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("86400"));
+        assert_eq!(retry_after(&headers, now), Some(RETRY_AFTER_MAX));
+    }
+}
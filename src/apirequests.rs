@@ -1,76 +1,387 @@
+use crate::apiclient::api_client;
 use crate::apitypes::*;
-use anyhow::Result;
-use reqwest::header::{self};
-use std::collections::HashMap;
+use crate::cache::MetadataCache;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use once_cell::sync::Lazy;
+use reqwest::{Method, StatusCode};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use tokio::sync::Mutex;
+use tokio::time::{Duration, Instant};
 
 use crate::config::*;
 
 // Speedrun.com API
 
-pub async fn get_latest_run(runner: &str) -> Result<Option<Run>> {
+async fn speedrun_request(url: &str) -> Result<String> {
+    let client = api_client();
+    let response = client.send(client.request(Method::GET, url)).await?;
+    Ok(response.text().await?)
+}
+
+// Find a user by their name, None if nobody is called that
+pub async fn get_user_by_name(name: &str) -> Result<Option<User>> {
+    let request_url = format!(
+        "https://www.speedrun.com/api/v1/users?lookup={name}",
+        name = name
+    );
+    let response = speedrun_request(&request_url).await?;
+    let data: UsersResponse = serde_json::from_str(&response)?;
+    Ok(data.data.into_iter().next())
+}
+
+// Get a user by their id, None if they no longer exist
+pub async fn get_user(user_id: &str) -> Result<Option<User>> {
+    let request_url = format!(
+        "https://www.speedrun.com/api/v1/users/{user}",
+        user = user_id
+    );
+    let response = speedrun_request(&request_url).await?;
+    let data: UserResponse = serde_json::from_str(&response)?;
+    Ok(data.data)
+}
+
+// Get all personal bests of a runner by their user id
+pub async fn get_personal_bests(user_id: &str) -> Result<Vec<Run>> {
     let request_url = format!(
-        "https://www.speedrun.com/api/v1/users/{runner}/personal-bests",
-        runner = runner
+        "https://www.speedrun.com/api/v1/users/{user}/personal-bests?embed=game,category.variables,level.variables,players",
+        user = user_id
     );
-    let response = reqwest::get(&request_url).await?.text().await?;
+    let response = speedrun_request(&request_url).await?;
     let data: RunResponse = serde_json::from_str(&response)?;
-    match data.data {
-        Some(runs) => {
-            let latest_run = runs.into_iter().fold(None, |max, x| match max {
-                None => Some(x),
-                Some(y) => Some(
-                    if y.run.status.verify_date.is_none()
-                        || x.run.status.verify_date > y.run.status.verify_date
-                    {
-                        x
-                    } else {
-                        y
-                    },
-                ),
-            });
-            Ok(latest_run)
+    Ok(data.data.unwrap_or_default())
+}
+
+// When a run was verified. None for unverified runs and for legacy runs
+// verified before speedrun.com recorded the date, which are never new.
+pub fn verified_at(run: &Run) -> Option<DateTime<Utc>> {
+    if run.run.status.status != "verified" {
+        return None;
+    }
+    let verify_date = run.run.status.verify_date.as_deref()?;
+    DateTime::parse_from_rfc3339(verify_date)
+        .ok()
+        .map(|date| date.with_timezone(&Utc))
+}
+
+// Order runs by verification time, breaking ties by id so the order is stable
+fn verification_order(a: &Run, b: &Run, a_at: DateTime<Utc>, b_at: DateTime<Utc>) -> Ordering {
+    a_at.cmp(&b_at).then_with(|| a.run.id.cmp(&b.run.id))
+}
+
+// Verified runs paired with their verification time, oldest first
+fn verified_runs<'a>(runs: impl Iterator<Item = &'a Run>) -> Vec<(&'a Run, DateTime<Utc>)> {
+    let mut verified: Vec<(&Run, DateTime<Utc>)> = runs
+        .filter_map(|run| verified_at(run).map(|at| (run, at)))
+        .collect();
+    verified.sort_by(|(a, a_at), (b, b_at)| verification_order(a, b, *a_at, *b_at));
+    verified
+}
+
+// Most recently verified personal best
+pub fn latest_run(runs: &[Run]) -> Option<&Run> {
+    verified_runs(runs.iter()).pop().map(|(run, _)| run)
+}
+
+// Personal bests verified since `since` that weren't announced yet, oldest
+// first. Older runs can become a PB again when a newer one is removed. Verify
+// dates only have seconds, so runs verified in the same second as the last
+// announced one still count
+pub fn new_runs<'a>(
+    runs: &'a [Run],
+    announced: &HashSet<String>,
+    since: Option<DateTime<Utc>>,
+) -> Vec<&'a Run> {
+    verified_runs(runs.iter().filter(|run| !announced.contains(&run.run.id)))
+        .into_iter()
+        .filter(|(_, at)| since.is_none_or(|since| *at >= since))
+        .map(|(run, _)| run)
+        .collect()
+}
+
+// Resolve the game, category, level, variables and players of a run from the
+// embedded data, falling back to cached requests for anything missing
+pub async fn get_run_details(cache: &MetadataCache, run: &Run) -> Result<RunDetails> {
+    let game = match &run.game {
+        Some(game) => game.data.clone(),
+        None => get_game_data(cache, &run.run.game)
+            .await?
+            .ok_or_else(|| anyhow!("Game {} not found", run.run.game))?,
+    };
+    let category = match &run.category {
+        Some(category) => category.data.clone(),
+        None => get_category_data(cache, &run.run.category)
+            .await?
+            .ok_or_else(|| anyhow!("Category {} not found", run.run.category))?,
+    };
+    let level = match (&run.run.level, &run.level) {
+        (None, _) => None,
+        (
+            Some(_),
+            Some(Embedded {
+                data: EmbeddedLevel::Level(level),
+            }),
+        ) => Some(level.clone()),
+        (Some(level), _) => get_level_data(cache, level).await?,
+    };
+
+    let embedded_variables: Vec<&Variable> = category
+        .variables
+        .iter()
+        .chain(level.iter().filter_map(|level| level.variables.as_ref()))
+        .flat_map(|variables| variables.data.iter())
+        .collect();
+    let mut labels: Vec<String> = Vec::new();
+    let mut missing: HashMap<String, String> = HashMap::new();
+    for (variable, value) in &run.run.values {
+        let label = embedded_variables
+            .iter()
+            .find(|v| &v.id == variable)
+            .and_then(|v| v.values.values.get(value));
+        match label {
+            Some(label) => labels.push(label.label.clone()),
+            None => {
+                missing.insert(variable.clone(), value.clone());
+            }
+        }
+    }
+    if let Some(variables) = get_variables(cache, missing).await? {
+        labels.push(variables);
+    }
+
+    let players = run.players.as_ref().map(|players| {
+        players
+            .data
+            .iter()
+            .filter_map(|player| match &player.names {
+                Some(names) => Some(names.international.clone()),
+                None => player.name.clone(),
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    });
+
+    Ok(RunDetails {
+        game,
+        category: category.name,
+        level: level.map(|level| level.name),
+        variables: if labels.is_empty() {
+            None
+        } else {
+            Some(labels.join(", "))
+        },
+        players: players.filter(|players| !players.is_empty()),
+    })
+}
+
+// Get a page of the most recently verified runs along with their players
+pub async fn get_recent_runs(offset: usize, max: usize) -> Result<Vec<RecentRun>> {
+    let request_url = format!(
+        "https://www.speedrun.com/api/v1/runs?status=verified&orderby=verify-date&direction=desc&embed=players&max={max}&offset={offset}",
+        max = max,
+        offset = offset
+    );
+    let response = speedrun_request(&request_url).await?;
+    let data: RecentRunsResponse = serde_json::from_str(&response)?;
+    Ok(data.data)
+}
+
+// Get the leaderboard a run is on, with the same variables and its players
+pub async fn get_leaderboard(run: &RunData) -> Result<Leaderboard> {
+    let mut request_url = match &run.level {
+        Some(level) => format!(
+            "https://www.speedrun.com/api/v1/leaderboards/{game}/level/{level}/{category}?embed=players",
+            game = run.game,
+            level = level,
+            category = run.category
+        ),
+        None => format!(
+            "https://www.speedrun.com/api/v1/leaderboards/{game}/category/{category}?embed=players",
+            game = run.game,
+            category = run.category
+        ),
+    };
+    for (variable, value) in &run.values {
+        request_url.push_str(&format!("&var-{}={}", variable, value));
+    }
+    let response = speedrun_request(&request_url).await?;
+    let data: LeaderboardResponse = serde_json::from_str(&response)?;
+    Ok(data.data)
+}
+
+// Get the runner's PB before this run along with the rank it would have on
+// the current leaderboard, None if this is their first run of the category
+pub async fn get_previous_pb(
+    run: &Run,
+    user_id: &str,
+    leaderboard: &Leaderboard,
+) -> Result<Option<PreviousPb>> {
+    let mut request_url = format!(
+        "https://www.speedrun.com/api/v1/runs?user={user}&game={game}&category={category}&status=verified&max=200",
+        user = user_id,
+        game = run.run.game,
+        category = run.run.category
+    );
+    if let Some(level) = &run.run.level {
+        request_url.push_str(&format!("&level={}", level));
+    }
+    let mut history: Vec<RunData> = Vec::new();
+    let mut next_url = Some(request_url);
+    while let Some(url) = next_url {
+        let response = speedrun_request(&url).await?;
+        let data: RunsResponse = serde_json::from_str(&response)?;
+        history.extend(data.data);
+        next_url = next_page(data.pagination);
+    }
+    let previous_time = match previous_pb(&run.run, &history) {
+        Some(previous) => previous.times.primary_t,
+        None => return Ok(None),
+    };
+
+    Ok(Some(PreviousPb {
+        time: previous_time,
+        rank: rank_of(previous_time, &leaderboard.runs, user_id),
+    }))
+}
+
+// Link to the next page of a list, None on the last page
+fn next_page(pagination: Option<Pagination>) -> Option<String> {
+    pagination?
+        .links
+        .into_iter()
+        .find(|link| link.rel == "next")
+        .map(|link| link.uri)
+}
+
+// World record, or a national record of one of the run's players when their
+// country is one of the given ones
+pub fn find_record(run: &Run, leaderboard: &Leaderboard, countries: &[String]) -> Option<Record> {
+    if run.place == 1 {
+        return Some(Record::World);
+    }
+
+    let mut player_countries: HashMap<&str, &Country> = HashMap::new();
+    let players = leaderboard.players.iter().chain(run.players.iter());
+    for player in players.flat_map(|players| players.data.iter()) {
+        if let (Some(id), Some(location)) = (&player.id, &player.location) {
+            player_countries.insert(id, &location.country);
         }
-        None => Ok(None),
     }
+    let country_of = |player: &RunPlayer| {
+        player
+            .id
+            .as_deref()
+            .and_then(|id| player_countries.get(id).copied())
+    };
+
+    let time = run.run.times.primary_t;
+    for country in run.run.players.iter().filter_map(country_of) {
+        if !countries
+            .iter()
+            .any(|code| code.eq_ignore_ascii_case(&country.code))
+        {
+            continue;
+        }
+        let beaten = leaderboard.runs.iter().any(|entry| {
+            entry.place > 0
+                && entry.run.id != run.run.id
+                && entry.run.times.primary_t < time
+                && entry
+                    .run
+                    .players
+                    .iter()
+                    .filter_map(country_of)
+                    .any(|other| other.code.eq_ignore_ascii_case(&country.code))
+        });
+        if !beaten {
+            return Some(Record::National(country.clone()));
+        }
+    }
+    None
 }
 
-pub async fn get_game_data(game: &str) -> Result<Option<Game>> {
+// Fastest run before this one with the same level and variables. Runs without
+// a valid date can't be placed before it
+fn previous_pb<'a>(current: &RunData, history: &'a [RunData]) -> Option<&'a RunData> {
+    let played = |run: &RunData| NaiveDate::parse_from_str(&run.date, "%Y-%m-%d").ok();
+    let current_played = played(current)?;
+    history
+        .iter()
+        .filter(|run| {
+            run.id != current.id
+                && run.level == current.level
+                && run.values == current.values
+                && played(run).is_some_and(|date| date <= current_played)
+        })
+        .min_by(|a, b| a.times.primary_t.total_cmp(&b.times.primary_t))
+}
+
+// Place a time would get on a leaderboard that the user isn't on
+fn rank_of(time: f64, leaderboard: &[Run], user_id: &str) -> u16 {
+    let faster = leaderboard
+        .iter()
+        .filter(|entry| {
+            entry.place > 0
+                && entry.run.times.primary_t < time
+                && !entry
+                    .run
+                    .players
+                    .iter()
+                    .any(|player| player.id.as_deref() == Some(user_id))
+        })
+        .count();
+    faster as u16 + 1
+}
+
+pub async fn get_game_data(cache: &MetadataCache, game: &str) -> Result<Option<Game>> {
     let request_url = format!("https://www.speedrun.com/api/v1/games/{game}", game = game);
-    let response = reqwest::get(&request_url).await?.text().await?;
+    let response = cache.get(&request_url).await?;
     let data: GameResponse = serde_json::from_str(&response)?;
     Ok(Some(data.data))
 }
 
-pub async fn get_category_data(category: &str) -> Result<Option<Category>> {
+pub async fn get_category_data(cache: &MetadataCache, category: &str) -> Result<Option<Category>> {
     let request_url = format!(
         "https://www.speedrun.com/api/v1/categories/{category}",
         category = category
     );
-    let response = reqwest::get(&request_url).await?.text().await?;
+    let response = cache.get(&request_url).await?;
     let data: CategoryResponse = serde_json::from_str(&response)?;
     Ok(Some(data.data))
 }
 
-pub async fn get_level_data(level: &str) -> Result<Option<Level>> {
+pub async fn get_level_data(cache: &MetadataCache, level: &str) -> Result<Option<Level>> {
     let request_url = format!(
         "https://www.speedrun.com/api/v1/levels/{level}",
         level = level
     );
-    let response = reqwest::get(&request_url).await?.text().await?;
+    let response = cache.get(&request_url).await?;
     let data: LevelResponse = serde_json::from_str(&response)?;
     Ok(Some(data.data))
 }
 
-pub async fn get_variables(values: HashMap<String, String>) -> Result<Option<String>> {
+pub async fn get_variables(
+    cache: &MetadataCache,
+    values: HashMap<String, String>,
+) -> Result<Option<String>> {
     let mut variables: Vec<String> = Vec::new();
     for (key, value) in values {
         let request_url = format!(
             "https://www.speedrun.com/api/v1/variables/{variable}",
             variable = key
         );
-        let response = reqwest::get(&request_url).await?.text().await?;
+        let response = cache.get(&request_url).await?;
         let data: VariableResponse = serde_json::from_str(&response)?;
-        variables.push(data.data.values.values[&value].label.clone());
+        // Values deleted from the variable since the run was submitted have no label
+        match data.data.values.values.get(&value) {
+            Some(variable) => variables.push(variable.label.clone()),
+            None => {
+                log::warn!("Variable {} has no value {}", key, value);
+                println!("[WARN] Variable {} has no value {}", key, value);
+            }
+        }
     }
     if variables.is_empty() {
         return Ok(None);
@@ -80,29 +391,94 @@ pub async fn get_variables(values: HashMap<String, String>) -> Result<Option<Str
 
 // Twitch API
 
-pub async fn get_twitch_user_id(user_name: &str) -> Result<Option<TwitchUser>> {
-    let request_url = format!(
-        "https://api.twitch.tv/helix/users?login={user_name}",
-        user_name = user_name
-    );
-    let mut headers = header::HeaderMap::new();
-    let twitch_oauth = get_config().twitch_oauth.as_str();
-    let client_id = get_config().twitch_client_id.as_str();
-    headers.insert(
-        header::AUTHORIZATION,
-        header::HeaderValue::from_str(&format!("Bearer {}", twitch_oauth)).unwrap(),
-    );
-    headers.insert("Client-Id", header::HeaderValue::from_static(&client_id));
+// App access token obtained through the client credentials flow
+struct TwitchToken {
+    access_token: String,
+    expires_at: Instant,
+}
+
+static TWITCH_TOKEN: Lazy<Mutex<Option<TwitchToken>>> = Lazy::new(|| Mutex::new(None));
+
+// Refresh the token this long before Twitch would expire it
+const TWITCH_TOKEN_MARGIN: Duration = Duration::from_secs(600);
 
-    let client = reqwest::Client::new();
+// Get a valid app access token, requesting a new one when the cached one is
+// about to expire or was rejected by Twitch
+async fn get_twitch_token(rejected: Option<&str>) -> Result<String> {
+    let mut token = TWITCH_TOKEN.lock().await;
+    if let Some(cached) = token.as_ref() {
+        let expiring = Instant::now() + TWITCH_TOKEN_MARGIN >= cached.expires_at;
+        if !expiring && rejected != Some(cached.access_token.as_str()) {
+            return Ok(cached.access_token.clone());
+        }
+    }
 
+    let config = get_config();
+    let client = api_client();
+    let request = client
+        .request(Method::POST, "https://id.twitch.tv/oauth2/token")
+        .form(&[
+            ("client_id", config.twitch_client_id.as_str()),
+            ("client_secret", config.twitch_client_secret.as_str()),
+            ("grant_type", "client_credentials"),
+        ]);
     let response = client
-        .get(request_url)
-        .headers(headers)
-        .send()
+        .send(request)
         .await?
+        .error_for_status()?
         .text()
         .await?;
+    let data: TwitchTokenResponse = serde_json::from_str(&response)?;
+    println!("[INFO] Got new Twitch access token");
+
+    let access_token = data.access_token.clone();
+    *token = Some(TwitchToken {
+        access_token: data.access_token,
+        expires_at: Instant::now() + Duration::from_secs(data.expires_in),
+    });
+    Ok(access_token)
+}
+
+// Send an authenticated Helix request, retrying once with a new token on 401
+async fn twitch_request(
+    method: Method,
+    url: &str,
+    query: &[(&str, &str)],
+    body: Option<&serde_json::Value>,
+) -> Result<String> {
+    let client = api_client();
+    let client_id = get_config().twitch_client_id.as_str();
+    let mut token = get_twitch_token(None).await?;
+    let mut retried = false;
+    loop {
+        let mut request = client
+            .request(method.clone(), url)
+            .bearer_auth(&token)
+            .header("Client-Id", client_id)
+            .query(query);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = client.send(request).await?;
+        if response.status() == StatusCode::UNAUTHORIZED && !retried {
+            log::warn!("Twitch rejected the access token, refreshing");
+            println!("[WARN] Twitch rejected the access token, refreshing");
+            token = get_twitch_token(Some(&token)).await?;
+            retried = true;
+            continue;
+        }
+        return Ok(response.error_for_status()?.text().await?);
+    }
+}
+
+pub async fn get_twitch_user_id(user_name: &str) -> Result<Option<TwitchUser>> {
+    let response = twitch_request(
+        Method::GET,
+        "https://api.twitch.tv/helix/users",
+        &[("login", user_name)],
+        None,
+    )
+    .await?;
 
     let data: TwitchUserResponse = serde_json::from_str(&response)?;
 
@@ -113,36 +489,330 @@ pub async fn get_twitch_user_id(user_name: &str) -> Result<Option<TwitchUser>> {
     }
 }
 
-pub async fn get_twitch_stream(user_id: &str) -> Result<Option<TwitchStream>> {
-    let request_url = format!(
-        "https://api.twitch.tv/helix/streams?user_id={user_id}",
-        user_id = user_id
-    );
+// Helix accepts at most 100 user ids per request
+const TWITCH_MAX_IDS: usize = 100;
 
-    let mut headers = header::HeaderMap::new();
-    let twitch_oauth = get_config().twitch_oauth.as_str();
-    let client_id = get_config().twitch_client_id.as_str();
-    headers.insert(
-        header::AUTHORIZATION,
-        header::HeaderValue::from_str(&format!("Bearer {}", twitch_oauth)).unwrap(),
-    );
-    headers.insert("Client-Id", header::HeaderValue::from_static(&client_id));
+// Get all live streams for the given Twitch user ids
+pub async fn get_twitch_streams(user_ids: &[String]) -> Result<Vec<TwitchStream>> {
+    let mut streams: Vec<TwitchStream> = Vec::new();
 
-    let client = reqwest::Client::new();
+    for chunk in user_ids.chunks(TWITCH_MAX_IDS) {
+        let mut cursor: Option<String> = None;
+        loop {
+            let mut query: Vec<(&str, &str)> =
+                chunk.iter().map(|id| ("user_id", id.as_str())).collect();
+            query.push(("first", "100"));
+            if let Some(after) = &cursor {
+                query.push(("after", after));
+            }
 
-    let response = client
-        .get(request_url)
-        .headers(headers)
-        .send()
-        .await?
-        .text()
+            let response = twitch_request(
+                Method::GET,
+                "https://api.twitch.tv/helix/streams",
+                &query,
+                None,
+            )
+            .await?;
+
+            let data: TwitchStreamResponse = serde_json::from_str(&response)?;
+            let page_len = data.data.len();
+            streams.extend(data.data);
+
+            cursor = data.pagination.and_then(|p| p.cursor);
+            if cursor.is_none() || page_len == 0 {
+                break;
+            }
+        }
+    }
+
+    Ok(streams)
+}
+
+// Get the archived VOD of a stream, if Twitch kept one
+pub async fn get_twitch_vod(user_id: &str, stream_id: &str) -> Result<Option<TwitchVideo>> {
+    let response = twitch_request(
+        Method::GET,
+        "https://api.twitch.tv/helix/videos",
+        &[("user_id", user_id), ("type", "archive"), ("first", "5")],
+        None,
+    )
+    .await?;
+
+    let data: TwitchVideoResponse = serde_json::from_str(&response)?;
+
+    Ok(data
+        .data
+        .into_iter()
+        .find(|video| video.stream_id.as_deref() == Some(stream_id)))
+}
+
+// Get every EventSub subscription of this app
+pub async fn get_eventsub_subscriptions() -> Result<Vec<EventSubSubscription>> {
+    let mut subscriptions: Vec<EventSubSubscription> = Vec::new();
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut query: Vec<(&str, &str)> = Vec::new();
+        if let Some(after) = &cursor {
+            query.push(("after", after));
+        }
+
+        let response = twitch_request(
+            Method::GET,
+            "https://api.twitch.tv/helix/eventsub/subscriptions",
+            &query,
+            None,
+        )
         .await?;
 
-    let data: TwitchStreamResponse = serde_json::from_str(&response)?;
+        let data: EventSubSubscriptionsResponse = serde_json::from_str(&response)?;
+        let page_len = data.data.len();
+        subscriptions.extend(data.data);
 
-    if let Some(twitch_stream) = data.data.first().cloned() {
-        Ok(Some(twitch_stream))
-    } else {
-        Ok(None)
+        cursor = data.pagination.and_then(|p| p.cursor);
+        if cursor.is_none() || page_len == 0 {
+            break;
+        }
+    }
+    Ok(subscriptions)
+}
+
+// Subscribe to an event of a broadcaster through our webhook
+pub async fn create_eventsub_subscription(kind: &str, version: &str, user_id: &str) -> Result<()> {
+    let config = get_config();
+    let body = json!({
+        "type": kind,
+        "version": version,
+        "condition": { "broadcaster_user_id": user_id },
+        "transport": {
+            "method": "webhook",
+            "callback": config.eventsub.callback_url,
+            "secret": config.eventsub.secret,
+        },
+    });
+    twitch_request(
+        Method::POST,
+        "https://api.twitch.tv/helix/eventsub/subscriptions",
+        &[],
+        Some(&body),
+    )
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_eventsub_subscription(id: &str) -> Result<()> {
+    twitch_request(
+        Method::DELETE,
+        "https://api.twitch.tv/helix/eventsub/subscriptions",
+        &[("id", id)],
+        None,
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> Vec<Run> {
+        let data: RunResponse = serde_json::from_str(json).expect("Invalid fixture");
+        data.data.unwrap_or_default()
+    }
+
+    fn personal_bests() -> Vec<Run> {
+        fixture(include_str!("../tests/fixtures/personal_bests.json"))
+    }
+
+    fn ids(runs: &[&Run]) -> Vec<String> {
+        runs.iter().map(|run| run.run.id.clone()).collect()
+    }
+
+    #[test]
+    fn parses_embedded_personal_bests() {
+        let runs = personal_bests();
+        assert_eq!(runs.len(), 3);
+        assert!(matches!(
+            runs[0].level.as_ref().map(|level| &level.data),
+            Some(EmbeddedLevel::None(_))
+        ));
+        match runs[1].level.as_ref().map(|level| &level.data) {
+            Some(EmbeddedLevel::Level(level)) => assert_eq!(level.name, "Bob-omb Battlefield"),
+            other => panic!("Expected an embedded level, got {:?}", other),
+        }
+        let players = &runs[1].players.as_ref().expect("Missing players").data;
+        assert_eq!(players.len(), 2);
+        assert_eq!(players[1].name.as_deref(), Some("friend"));
+    }
+
+    #[test]
+    fn parses_user_lookup_and_missing_user() {
+        let data: UsersResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/users.json")).unwrap();
+        assert_eq!(data.data.len(), 1);
+        assert_eq!(data.data[0].id, "18qyezox");
+        assert_eq!(data.data[0].names.international, "Runner");
+
+        let missing: UserResponse =
+            serde_json::from_str(r#"{"status": 404, "message": "User not found."}"#).unwrap();
+        assert!(missing.data.is_none());
+    }
+
+    #[test]
+    fn latest_run_compares_timestamps_not_strings() {
+        // 23:30+02:00 sorts after 22:00Z as a string but happened before it
+        let runs = personal_bests();
+        assert_eq!(latest_run(&runs).unwrap().run.id, "zpq9kl3y");
+    }
+
+    #[test]
+    fn latest_run_ignores_order_and_runs_without_verify_date() {
+        let mut runs = personal_bests();
+        runs.rotate_left(2);
+        assert_eq!(latest_run(&runs).unwrap().run.id, "zpq9kl3y");
+        runs.reverse();
+        assert_eq!(latest_run(&runs).unwrap().run.id, "zpq9kl3y");
+    }
+
+    #[test]
+    fn latest_run_skips_unverified_runs() {
+        let mut runs = personal_bests();
+        runs[0].run.status.status = String::from("new");
+        runs[0].run.status.verify_date = Some(String::from("2024-01-01T00:00:00Z"));
+        runs[1].run.status.status = String::from("rejected");
+        assert!(latest_run(&runs).is_none());
+    }
+
+    #[test]
+    fn latest_run_of_no_runs_is_none() {
+        let runs = fixture(include_str!("../tests/fixtures/personal_bests_empty.json"));
+        assert!(latest_run(&runs).is_none());
+        assert!(new_runs(&runs, &HashSet::new(), None).is_empty());
+    }
+
+    #[test]
+    fn previous_pb_is_fastest_earlier_run_of_same_leaderboard() {
+        let runs = personal_bests();
+        let current = &runs[0].run;
+        let data: RunsResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/runs.json")).unwrap();
+        let previous = previous_pb(current, &data.data).expect("No previous PB");
+        assert_eq!(previous.id, "8yv6l3nm");
+        assert!(previous_pb(current, &[]).is_none());
+        assert!(next_page(data.pagination).is_none());
+
+        // A faster run without a valid date doesn't count as earlier
+        let mut history = data.data;
+        let undated = history
+            .iter_mut()
+            .find(|run| run.id != current.id && run.id != "8yv6l3nm")
+            .unwrap();
+        undated.date = String::from("unknown");
+        undated.level = current.level.clone();
+        undated.values = current.values.clone();
+        undated.times.primary_t = 1.0;
+        let previous = previous_pb(current, &history).expect("No previous PB");
+        assert_eq!(previous.id, "8yv6l3nm");
+    }
+
+    #[test]
+    fn follows_the_next_page_link() {
+        let data: RunsResponse = serde_json::from_str(
+            r#"{"data": [], "pagination": {"offset": 0, "max": 200, "size": 200, "links": [
+                {"rel": "prev", "uri": "https://www.speedrun.com/api/v1/runs?offset=0"},
+                {"rel": "next", "uri": "https://www.speedrun.com/api/v1/runs?offset=200"}
+            ]}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            next_page(data.pagination).as_deref(),
+            Some("https://www.speedrun.com/api/v1/runs?offset=200")
+        );
+    }
+
+    #[test]
+    fn rank_of_ignores_the_runners_own_entry() {
+        let data: LeaderboardResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/leaderboard.json")).unwrap();
+        let runs = &data.data.runs;
+        assert_eq!(rank_of(6100.0, runs, "18qyezox"), 3);
+        assert_eq!(rank_of(5000.0, runs, "18qyezox"), 1);
+        assert_eq!(rank_of(7000.0, runs, "18qyezox"), 5);
+    }
+
+    #[test]
+    fn finds_world_and_national_records() {
+        let data: LeaderboardResponse =
+            serde_json::from_str(include_str!("../tests/fixtures/leaderboard.json")).unwrap();
+        let leaderboard = &data.data;
+        let countries = vec![String::from("cz"), String::from("sk")];
+        let mut runs = personal_bests();
+        let run = &mut runs[0];
+
+        // Third place, and the only Czech player faster is the runner themselves
+        match find_record(run, leaderboard, &countries) {
+            Some(Record::National(country)) => assert_eq!(country.code, "cz"),
+            other => panic!("Expected a national record, got {:?}", other),
+        }
+        assert!(find_record(run, leaderboard, &[String::from("sk")]).is_none());
+
+        // A faster Slovak player doesn't take the Czech record
+        run.run.times.primary_t = 6050.0;
+        assert!(find_record(run, leaderboard, &countries).is_some());
+        // A faster Czech player does
+        run.run.times.primary_t = 6500.0;
+        assert!(find_record(run, leaderboard, &countries).is_none());
+
+        run.place = 1;
+        assert!(matches!(
+            find_record(run, leaderboard, &countries),
+            Some(Record::World)
+        ));
+    }
+
+    #[test]
+    fn new_runs_are_oldest_first_and_skip_announced() {
+        let runs = personal_bests();
+        assert_eq!(
+            ids(&new_runs(&runs, &HashSet::new(), None)),
+            vec!["y8d4p3km", "zpq9kl3y"]
+        );
+
+        let announced = HashSet::from([String::from("y8d4p3km")]);
+        assert_eq!(ids(&new_runs(&runs, &announced, None)), vec!["zpq9kl3y"]);
+    }
+
+    #[test]
+    fn new_runs_skip_runs_verified_before_the_cutoff() {
+        let runs = personal_bests();
+        let since = |date: &str| Some(DateTime::parse_from_rfc3339(date).unwrap().into());
+        assert_eq!(
+            ids(&new_runs(
+                &runs,
+                &HashSet::new(),
+                since("2023-05-01T21:45:00Z")
+            )),
+            vec!["zpq9kl3y"]
+        );
+
+        // The latest PB was announced and then removed, so the older PB that
+        // was never announced is the PB again
+        let mut runs = personal_bests();
+        runs.retain(|run| run.run.id != "zpq9kl3y");
+        let announced = HashSet::from([String::from("zpq9kl3y")]);
+        assert!(new_runs(&runs, &announced, since("2023-05-01T22:00:00Z")).is_empty());
+
+        // Both PBs verified in the same second, the first one was just announced
+        let mut runs = personal_bests();
+        for run in runs
+            .iter_mut()
+            .filter(|run| run.run.status.verify_date.is_some())
+        {
+            run.run.status.verify_date = Some(String::from("2023-05-01T22:00:00Z"));
+        }
+        let announced = HashSet::from([String::from("y8d4p3km")]);
+        assert_eq!(
+            ids(&new_runs(&runs, &announced, since("2023-05-01T22:00:00Z"))),
+            vec!["zpq9kl3y"]
+        );
     }
 }
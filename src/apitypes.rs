@@ -1,10 +1,9 @@
 use serde::Deserialize;
-use serenity::all::MessageId;
 use std::collections::HashMap;
 
 // Speedrun.com API
 // Run
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct RunData {
     pub id: String,
     pub category: String,
@@ -15,21 +14,93 @@ pub struct RunData {
     pub weblink: String,
     pub times: Times,
     pub date: String,
+    #[serde(default)]
+    pub players: Vec<RunPlayer>,
 }
 
-#[derive(Deserialize, Debug)]
+// Player of a run as referenced by the run itself
+#[derive(Deserialize, Debug, Clone)]
+pub struct RunPlayer {
+    // "user" or "guest"
+    pub rel: String,
+    pub id: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct Times {
     pub primary_t: f64,
 }
 
+// Personal best fetched with embed=game,category.variables,level.variables,players
 #[derive(Deserialize, Debug)]
 pub struct Run {
     pub place: u16,
     pub run: RunData,
+    #[serde(default)]
+    pub game: Option<Embedded<Game>>,
+    #[serde(default)]
+    pub category: Option<Embedded<Category>>,
+    #[serde(default)]
+    pub level: Option<Embedded<EmbeddedLevel>>,
+    #[serde(default)]
+    pub players: Option<Embedded<Vec<Player>>>,
 }
 
-#[derive(Deserialize, Debug)]
+// Resource included in a response through embed=
+#[derive(Deserialize, Debug, Clone)]
+pub struct Embedded<T> {
+    pub data: T,
+}
+
+// Full game runs embed an empty list instead of a level
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum EmbeddedLevel {
+    Level(Level),
+    None(Vec<serde_json::Value>),
+}
+
+// Registered user or guest taking part in a run
+#[derive(Deserialize, Debug, Clone)]
+pub struct Player {
+    // Only set for users
+    pub id: Option<String>,
+    pub names: Option<UserNames>,
+    // Only set for guests
+    pub name: Option<String>,
+    #[serde(default)]
+    pub location: Option<Location>,
+    pub weblink: Option<String>,
+}
+
+// Where a user is from, if they chose to share it
+#[derive(Deserialize, Debug, Clone)]
+pub struct Location {
+    pub country: Country,
+    #[serde(default)]
+    pub region: Option<Country>,
+}
+
+// Country or region of a country
+#[derive(Deserialize, Debug, Clone)]
+pub struct Country {
+    // ISO 3166-1 alpha-2 in lowercase, regions add their own code like "us/ca"
+    pub code: String,
+    pub names: UserNames,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UserNames {
+    pub international: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct RunStatus {
+    // "new", "verified" or "rejected"
+    #[serde(default)]
+    pub status: String,
+    // Missing for runs verified before speedrun.com recorded it
     #[serde(rename = "verify-date")]
     pub verify_date: Option<String>,
 }
@@ -39,31 +110,94 @@ pub struct RunResponse {
     pub data: Option<Vec<Run>>,
 }
 
+// Runs of a user, from /runs
+#[derive(Deserialize, Debug)]
+pub struct RunsResponse {
+    pub data: Vec<RunData>,
+    pub pagination: Option<Pagination>,
+}
+
+// Position in a list split into pages, with a "next" link unless it's the last
+#[derive(Deserialize, Debug)]
+pub struct Pagination {
+    #[serde(default)]
+    pub links: Vec<Link>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Link {
+    pub rel: String,
+    pub uri: String,
+}
+
+// Recently verified runs, fetched with embed=players
+#[derive(Deserialize, Debug)]
+pub struct RecentRunsResponse {
+    pub data: Vec<RecentRun>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RecentRun {
+    pub players: Embedded<Vec<Player>>,
+}
+
+// Users matching a name, from /users?lookup=
+#[derive(Deserialize, Debug)]
+pub struct UsersResponse {
+    pub data: Vec<User>,
+}
+
+// One user, without data when they don't exist
+#[derive(Deserialize, Debug)]
+pub struct UserResponse {
+    pub data: Option<User>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct User {
+    pub id: String,
+    pub names: UserNames,
+}
+
+// Leaderboard
+#[derive(Deserialize, Debug)]
+pub struct LeaderboardResponse {
+    pub data: Leaderboard,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Leaderboard {
+    pub runs: Vec<Run>,
+    // Fetched with embed=players
+    #[serde(default)]
+    pub players: Option<Embedded<Vec<Player>>>,
+}
+
 // Game
 #[derive(Deserialize, Debug)]
 pub struct GameResponse {
     pub data: Game,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Game {
     pub id: String,
     pub names: GameNames,
     pub assets: GameAssets,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct GameNames {
     pub international: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct GameAssets {
     #[serde(rename = "cover-medium")]
     pub cover_medium: Asset,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Asset {
     pub uri: String,
 }
@@ -74,9 +208,11 @@ pub struct CategoryResponse {
     pub data: Category,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Category {
     pub name: String,
+    #[serde(default)]
+    pub variables: Option<Embedded<Vec<Variable>>>,
 }
 
 // Level
@@ -85,9 +221,11 @@ pub struct LevelResponse {
     pub data: Level,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Level {
     pub name: String,
+    #[serde(default)]
+    pub variables: Option<Embedded<Vec<Variable>>>,
 }
 
 // Variable
@@ -96,23 +234,56 @@ pub struct VariableResponse {
     pub data: Variable,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Variable {
+    pub id: String,
     pub values: VariableValues,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct VariableValues {
     pub values: HashMap<String, VariableLabel>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct VariableLabel {
     pub label: String,
 }
 
+// Game, category, level, variables and players of a run, ready to be posted
+#[derive(Debug)]
+pub struct RunDetails {
+    pub game: Game,
+    pub category: String,
+    pub level: Option<String>,
+    pub variables: Option<String>,
+    pub players: Option<String>,
+}
+
+// Runner's PB before the new one
+#[derive(Debug)]
+pub struct PreviousPb {
+    pub time: f64,
+    // Where the previous time would place on the leaderboard now
+    pub rank: u16,
+}
+
+// Record set by a new PB
+#[derive(Debug, Clone)]
+pub enum Record {
+    World,
+    // Best time on the leaderboard among players from this country
+    National(Country),
+}
+
 // Twitch API
 
+#[derive(Deserialize, Debug)]
+pub struct TwitchTokenResponse {
+    pub access_token: String,
+    pub expires_in: u64,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct TwitchUserResponse {
     pub data: Vec<TwitchUser>,
@@ -126,15 +297,76 @@ pub struct TwitchUser {
 #[derive(Deserialize, Debug)]
 pub struct TwitchStreamResponse {
     pub data: Vec<TwitchStream>,
+    pub pagination: Option<TwitchPagination>,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct TwitchPagination {
+    pub cursor: Option<String>,
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct TwitchStream {
+    pub id: String,
+    pub user_id: String,
     pub user_name: String,
     pub title: String,
     pub game_name: String,
     pub thumbnail_url: String,
+    pub viewer_count: u64,
+    pub started_at: String,
+    #[serde(default)]
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TwitchVideoResponse {
+    pub data: Vec<TwitchVideo>,
 }
 
-pub type StreamMessage = HashMap<String, MessageId>;
\ No newline at end of file
+#[derive(Deserialize, Debug, Clone)]
+pub struct TwitchVideo {
+    pub stream_id: Option<String>,
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EventSubSubscriptionsResponse {
+    pub data: Vec<EventSubSubscription>,
+    pub pagination: Option<TwitchPagination>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventSubSubscription {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status: String,
+    pub condition: EventSubCondition,
+    pub transport: EventSubTransport,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventSubCondition {
+    pub broadcaster_user_id: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventSubTransport {
+    pub callback: Option<String>,
+}
+
+// Body of a webhook sent by Twitch
+#[derive(Deserialize, Debug)]
+pub struct EventSubPayload {
+    pub subscription: EventSubSubscription,
+    pub challenge: Option<String>,
+    pub event: Option<EventSubEvent>,
+}
+
+// Event of a stream.online, stream.offline or channel.update notification
+#[derive(Deserialize, Debug, Clone)]
+pub struct EventSubEvent {
+    pub broadcaster_user_id: String,
+    pub broadcaster_user_login: String,
+}
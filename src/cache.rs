@@ -0,0 +1,230 @@
+use anyhow::Result;
+use chrono::Utc;
+use lru::LruCache;
+use reqwest::Method;
+use serenity::prelude::*;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
+use crate::apiclient::api_client;
+use crate::config::get_config;
+use crate::database::{CachedResponse, Database};
+
+// speedrun.com metadata responses keyed by URL, kept in memory and in the
+// database so they survive restarts and speedrun.com outages
+pub struct MetadataCache {
+    db: Arc<Database>,
+    memory: std::sync::Mutex<LruCache<String, CachedResponse>>,
+    // Seconds a response stays fresh
+    ttl: i64,
+}
+
+impl TypeMapKey for MetadataCache {
+    type Value = Arc<MetadataCache>;
+}
+
+pub async fn get_metadata_cache(ctx: &Context) -> Arc<MetadataCache> {
+    let data = ctx.data.read().await;
+    Arc::clone(
+        data.get::<MetadataCache>()
+            .expect("MetadataCache is not in the TypeMap"),
+    )
+}
+
+impl MetadataCache {
+    pub fn new(db: Arc<Database>) -> Self {
+        let config = &get_config().cache;
+        let size = NonZeroUsize::new(config.size).unwrap_or(NonZeroUsize::MIN);
+        MetadataCache::with_limits(db, size, config.metadata_ttl as i64)
+    }
+
+    fn with_limits(db: Arc<Database>, size: NonZeroUsize, ttl: i64) -> Self {
+        MetadataCache {
+            db,
+            memory: std::sync::Mutex::new(LruCache::new(size)),
+            ttl,
+        }
+    }
+
+    // Get the body of a speedrun.com request, fetching it only when the cached
+    // one expired. An expired body is still returned if the request fails.
+    pub async fn get(&self, url: &str) -> Result<String> {
+        self.get_or_fetch(url, fetch).await
+    }
+
+    async fn get_or_fetch<F, R>(&self, url: &str, fetch: F) -> Result<String>
+    where
+        F: FnOnce(String) -> R,
+        R: Future<Output = Result<String>>,
+    {
+        let now = Utc::now().timestamp();
+        let mut cached = self.memory().get(url).cloned();
+        if cached.is_none() {
+            cached = match self.db.get_cached_response(url).await {
+                Ok(cached) => cached,
+                Err(why) => {
+                    log::error!("Failed to read cached response: {:?}", why);
+                    println!("[ERROR] Failed to read cached response: {:?}", why);
+                    None
+                }
+            };
+            if let Some(cached) = &cached {
+                self.memory().put(url.to_string(), cached.clone());
+            }
+        }
+        if let Some(cached) = &cached {
+            if now - cached.fetched_at < self.ttl {
+                return Ok(cached.body.clone());
+            }
+        }
+
+        match fetch(url.to_string()).await {
+            Ok(body) => {
+                let fresh = CachedResponse {
+                    body: body.clone(),
+                    fetched_at: now,
+                };
+                if let Err(why) = self.db.set_cached_response(url, &fresh).await {
+                    log::error!("Failed to cache response: {:?}", why);
+                    println!("[ERROR] Failed to cache response: {:?}", why);
+                }
+                self.memory().put(url.to_string(), fresh);
+                Ok(body)
+            }
+            Err(why) => match cached {
+                Some(cached) => {
+                    log::warn!("Using expired cache for {}: {:?}", url, why);
+                    println!("[WARN] Using expired cache for {}", url);
+                    Ok(cached.body)
+                }
+                None => Err(why),
+            },
+        }
+    }
+
+    fn memory(&self) -> std::sync::MutexGuard<'_, LruCache<String, CachedResponse>> {
+        self.memory.lock().expect("Metadata cache lock poisoned")
+    }
+}
+
+// Only successful responses are worth caching
+async fn fetch(url: String) -> Result<String> {
+    let client = api_client();
+    let response = client.send(client.request(Method::GET, &url)).await?;
+    Ok(response.error_for_status()?.text().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::connect_in_memory;
+    use anyhow::anyhow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const GAME: &str = "https://www.speedrun.com/api/v1/games/o1y9wo6q";
+    const LEVEL: &str = "https://www.speedrun.com/api/v1/levels/wkpoo02r";
+
+    fn cache(size: usize, ttl: i64) -> MetadataCache {
+        let size = NonZeroUsize::new(size).unwrap();
+        MetadataCache::with_limits(Arc::new(connect_in_memory()), size, ttl)
+    }
+
+    // Counts the requests and answers them with the given body, or fails
+    struct Api {
+        requests: AtomicUsize,
+    }
+
+    impl Api {
+        fn new() -> Self {
+            Api {
+                requests: AtomicUsize::new(0),
+            }
+        }
+
+        async fn answer(&self, body: Option<&str>) -> Result<String> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            body.map(String::from)
+                .ok_or_else(|| anyhow!("speedrun.com is down"))
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    #[tokio::test]
+    async fn fresh_responses_are_not_requested_again() {
+        let cache = cache(10, 60);
+        let api = Api::new();
+        for _ in 0..3 {
+            let body = cache
+                .get_or_fetch(GAME, |_| api.answer(Some("game")))
+                .await
+                .unwrap();
+            assert_eq!(body, "game");
+        }
+        assert_eq!(api.requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_responses_are_requested_again() {
+        let cache = cache(10, 0);
+        let api = Api::new();
+        cache
+            .get_or_fetch(GAME, |_| api.answer(Some("old")))
+            .await
+            .unwrap();
+        let body = cache
+            .get_or_fetch(GAME, |_| api.answer(Some("new")))
+            .await
+            .unwrap();
+        assert_eq!(body, "new");
+        assert_eq!(api.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn responses_evicted_from_memory_come_from_the_database() {
+        let cache = cache(1, 60);
+        let api = Api::new();
+        cache
+            .get_or_fetch(GAME, |_| api.answer(Some("game")))
+            .await
+            .unwrap();
+        // Pushes the game out of memory
+        cache
+            .get_or_fetch(LEVEL, |_| api.answer(Some("level")))
+            .await
+            .unwrap();
+        assert!(cache.memory().get(GAME).is_none());
+
+        let body = cache
+            .get_or_fetch(GAME, |_| api.answer(None))
+            .await
+            .unwrap();
+        assert_eq!(body, "game");
+        assert_eq!(api.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn expired_responses_are_served_when_requests_fail() {
+        let cache = cache(10, 0);
+        let api = Api::new();
+        cache
+            .get_or_fetch(GAME, |_| api.answer(Some("game")))
+            .await
+            .unwrap();
+        let body = cache
+            .get_or_fetch(GAME, |_| api.answer(None))
+            .await
+            .unwrap();
+        assert_eq!(body, "game");
+        assert_eq!(api.requests(), 2);
+
+        // Nothing to fall back on for responses never fetched
+        assert!(cache
+            .get_or_fetch(LEVEL, |_| api.answer(None))
+            .await
+            .is_err());
+    }
+}
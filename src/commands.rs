@@ -0,0 +1,1193 @@
+use serenity::{
+    all::{
+        ButtonStyle, ChannelId, ChannelType, Colour, Command, CommandInteraction,
+        CommandOptionType, ComponentInteraction, CreateActionRow, CreateAutocompleteResponse,
+        CreateButton, CreateCommand, CreateCommandOption, CreateEmbed, CreateEmbedFooter,
+        CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
+        GuildId, Member, ResolvedOption, ResolvedValue, RoleId, UserId,
+    },
+    prelude::*,
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::sync::Arc;
+
+use crate::apirequests::*;
+use crate::apitypes::{Run, User};
+use crate::config;
+use crate::database::*;
+use crate::eventsub;
+use crate::filters::{self, FilterKind};
+use crate::locale::{guild_language, locale, Language, Locale};
+use crate::templates::Values;
+
+// Discord allows at most 25 autocomplete choices
+const MAX_CHOICES: usize = 25;
+// Entries shown on one page of /runner list and /streamer list
+const PAGE_SIZE: usize = 15;
+// Options of /settings, which can also be cleared
+const SETTINGS: [&str; 5] = ["runs", "streams", "records", "admin_role", "records_role"];
+// Links of a /member, which can also be cleared
+const MEMBER_LINKS: [&str; 4] = ["runner", "streamer", "discord", "youtube"];
+
+// Register all slash commands, replacing any previously registered ones
+pub async fn register(ctx: &Context) {
+    let commands = vec![
+        CreateCommand::new("runner")
+            .description("Manage tracked speedrun.com runners")
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "add",
+                    "Start tracking a runner",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "name",
+                        "speedrun.com user name",
+                    )
+                    .required(true),
+                ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "remove",
+                    "Stop tracking a runner",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::String, "name", "Tracked runner")
+                        .required(true)
+                        .set_autocomplete(true),
+                ),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "list",
+                "List tracked runners",
+            )),
+        CreateCommand::new("streamer")
+            .description("Manage tracked Twitch streamers")
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "add",
+                    "Start tracking a streamer",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "name",
+                        "Twitch login name",
+                    )
+                    .required(true),
+                ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "remove",
+                    "Stop tracking a streamer",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::String, "name", "Tracked streamer")
+                        .required(true)
+                        .set_autocomplete(true),
+                ),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "list",
+                "List tracked streamers",
+            )),
+        CreateCommand::new("filter")
+            .description("Manage which live streams get announced")
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::SubCommand, "add", "Add a filter rule")
+                    .add_sub_option(
+                        FilterKind::ALL.iter().fold(
+                            CreateCommandOption::new(
+                                CommandOptionType::String,
+                                "kind",
+                                "Kind of rule",
+                            )
+                            .required(true),
+                            |option, kind| {
+                                option.add_string_choice(kind.description(), kind.as_str())
+                            },
+                        ),
+                    )
+                    .add_sub_option(
+                        CreateCommandOption::new(
+                            CommandOptionType::String,
+                            "value",
+                            "Game name, tag or regex",
+                        )
+                        .required(true),
+                    )
+                    .add_sub_option(
+                        CreateCommandOption::new(
+                            CommandOptionType::String,
+                            "streamer",
+                            "Only apply to this streamer instead of everyone",
+                        )
+                        .set_autocomplete(true),
+                    ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "remove",
+                    "Remove a filter rule",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::Integer, "id", "Rule id")
+                        .required(true),
+                ),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "list",
+                    "List filter rules",
+                )
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "streamer",
+                        "Only show the rules applying to this streamer",
+                    )
+                    .set_autocomplete(true),
+                ),
+            ),
+        CreateCommand::new("language")
+            .description("Change the language of bot messages in this server")
+            .dm_permission(false)
+            .add_option(
+                Language::ALL.iter().fold(
+                    CreateCommandOption::new(
+                        CommandOptionType::String,
+                        "language",
+                        "Language of replies and announcements",
+                    )
+                    .required(true),
+                    |option, language| {
+                        option.add_string_choice(language.native_name(), language.code())
+                    },
+                ),
+            ),
+        CreateCommand::new("settings")
+            .description("Show or change where this server's announcements go")
+            .dm_permission(false)
+            .add_option(channel_option("runs", "Channel for new personal bests"))
+            .add_option(channel_option("streams", "Channel for live streams"))
+            .add_option(channel_option(
+                "records",
+                "Channel for world and national records, the runs channel when not set",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Role,
+                "admin_role",
+                "Role allowed to manage the bot besides members who can manage the server",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::Role,
+                "records_role",
+                "Role pinged when a record is posted",
+            ))
+            .add_option(SETTINGS.iter().fold(
+                CreateCommandOption::new(CommandOptionType::String, "clear", "Unset a setting"),
+                |option, setting| option.add_string_choice(*setting, *setting),
+            )),
+        CreateCommand::new("member")
+            .description("Show or change the accounts linked to one person")
+            .dm_permission(false)
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "name",
+                    "Member, named after their runner or streamer",
+                )
+                .required(true)
+                .set_autocomplete(true),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "runner", "Tracked runner")
+                    .set_autocomplete(true),
+            )
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::String, "streamer", "Tracked streamer")
+                    .set_autocomplete(true),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "discord",
+                "Discord account",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "youtube",
+                "YouTube channel link, @handle or channel id",
+            ))
+            .add_option(MEMBER_LINKS.iter().fold(
+                CreateCommandOption::new(CommandOptionType::String, "clear", "Unlink an account"),
+                |option, link| option.add_string_choice(*link, *link),
+            )),
+    ];
+
+    if let Err(why) = Command::set_global_commands(&ctx.http, commands).await {
+        log::error!("Failed to register commands: {:?}", why);
+        println!("[ERROR] Failed to register commands: {:?}", why);
+    }
+}
+
+fn channel_option(name: &str, description: &str) -> CreateCommandOption {
+    CreateCommandOption::new(CommandOptionType::Channel, name, description)
+        .channel_types(vec![ChannelType::Text, ChannelType::News])
+}
+
+// Run a slash command and reply to the admin with the outcome
+pub async fn run(ctx: &Context, command: &CommandInteraction) {
+    if let Err(why) = command.defer_ephemeral(&ctx.http).await {
+        log::error!("Failed to defer response: {:?}", why);
+        println!("[ERROR] Failed to defer response: {:?}", why);
+        return;
+    }
+
+    let texts = locale(guild_language(ctx, command.guild_id).await);
+    let guild_id = match command.guild_id {
+        Some(guild_id) if is_admin(ctx, guild_id, command.member.as_deref()).await => guild_id,
+        _ => {
+            let builder = EditInteractionResponse::new().content(texts.text("not_allowed"));
+            return edit_response(ctx, command, builder).await;
+        }
+    };
+
+    let options = command.data.options();
+    let reply = match command.data.name.as_str() {
+        "language" => set_language(ctx, guild_id, string_option(&options, "language"), texts).await,
+        "settings" => update_settings(ctx, guild_id, &options, texts).await,
+        "member" => edit_member(ctx, guild_id, &options, texts).await,
+        _ => match subcommand(&options) {
+            Some((subcommand, sub_options)) => match (
+                command.data.name.as_str(),
+                subcommand,
+                string_option(sub_options, "name"),
+            ) {
+                ("runner", "add", Some(name)) => {
+                    match add_runner(ctx, guild_id, name, command.user.id, texts).await {
+                        Ok(reply) | Err(reply) => reply,
+                    }
+                }
+                ("runner", "remove", Some(name)) => remove_runner(ctx, guild_id, name, texts).await,
+                ("streamer", "add", Some(name)) => {
+                    add_streamer(ctx, guild_id, name, command.user.id, texts).await
+                }
+                ("streamer", "remove", Some(name)) => {
+                    remove_streamer(ctx, guild_id, name, texts).await
+                }
+                ("runner", "list", _) | ("streamer", "list", _) => {
+                    let (embed, buttons) =
+                        list_page(ctx, guild_id, &command.data.name, 0, texts).await;
+                    return edit_response(
+                        ctx,
+                        command,
+                        EditInteractionResponse::new()
+                            .embed(embed)
+                            .components(buttons),
+                    )
+                    .await;
+                }
+                ("filter", "add", _) => {
+                    add_filter(ctx, guild_id, sub_options, command.user.id, texts).await
+                }
+                ("filter", "remove", _) => remove_filter(ctx, guild_id, sub_options, texts).await,
+                ("filter", "list", _) => list_filters(ctx, guild_id, sub_options, texts).await,
+                _ => texts.text("unknown_command"),
+            },
+            None => texts.text("unknown_command"),
+        },
+    };
+
+    edit_response(ctx, command, EditInteractionResponse::new().content(reply)).await;
+}
+
+// Turn the page of a /runner list or /streamer list embed
+pub async fn component(ctx: &Context, component: &ComponentInteraction) {
+    // Custom ids look like "list:runner:3" or "discover:approve:<user id>"
+    let mut parts = component.data.custom_id.split(':');
+    let action = (parts.next(), parts.next(), parts.next());
+    if !matches!(action, (Some("list" | "discover"), Some(_), Some(_))) {
+        return;
+    }
+
+    let texts = locale(guild_language(ctx, component.guild_id).await);
+    let guild_id = match component.guild_id {
+        Some(guild_id) if is_admin(ctx, guild_id, component.member.as_ref()).await => guild_id,
+        _ => {
+            let builder = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(texts.text("not_allowed"))
+                    .ephemeral(true),
+            );
+            if let Err(why) = component.create_response(&ctx.http, builder).await {
+                log::error!("Failed to respond to button: {:?}", why);
+                println!("[ERROR] Failed to respond to button: {:?}", why);
+            }
+            return;
+        }
+    };
+
+    match action {
+        (Some("list"), Some(kind), Some(page)) => {
+            if let Ok(page) = page.parse::<usize>() {
+                list_component(ctx, component, guild_id, kind, page, texts).await;
+            }
+        }
+        (Some("discover"), Some(decision), Some(user_id)) => {
+            let approved = decision == "approve";
+            discover_component(ctx, component, guild_id, approved, user_id, texts).await;
+        }
+        _ => {}
+    }
+}
+
+async fn list_component(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    guild_id: GuildId,
+    kind: &str,
+    page: usize,
+    texts: &Locale,
+) {
+    let (embed, buttons) = list_page(ctx, guild_id, kind, page, texts).await;
+    let builder = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .components(buttons),
+    );
+    if let Err(why) = component.create_response(&ctx.http, builder).await {
+        log::error!("Failed to update list: {:?}", why);
+        println!("[ERROR] Failed to update list: {:?}", why);
+    }
+}
+
+// Start tracking a runner proposed by discovery, or ignore them for good
+async fn discover_component(
+    ctx: &Context,
+    component: &ComponentInteraction,
+    guild_id: GuildId,
+    approved: bool,
+    user_id: &str,
+    texts: &Locale,
+) {
+    // Adding a runner fetches their PBs, which can take longer than Discord waits
+    if let Err(why) = component.defer(&ctx.http).await {
+        log::error!("Failed to acknowledge button: {:?}", why);
+        println!("[ERROR] Failed to acknowledge button: {:?}", why);
+        return;
+    }
+
+    let db = get_database(ctx).await;
+    let reply = match db
+        .decide_discovered_runner(user_id, approved, component.user.id)
+        .await
+    {
+        Ok(Some(runner)) if approved => {
+            // Found by user id already, so no lookup by a name that may have changed
+            match add_user(
+                ctx,
+                guild_id,
+                &runner.user_id,
+                &runner.name,
+                component.user.id,
+                texts,
+            )
+            .await
+            {
+                Ok(reply) => reply,
+                // Left pending so the runner can be approved again
+                Err(reply) => {
+                    if let Err(why) = db.reopen_discovered_runner(user_id).await {
+                        log::error!("Failed to reopen found runner {}: {:?}", user_id, why);
+                        println!("[ERROR] Failed to reopen found runner");
+                    }
+                    reply
+                }
+            }
+        }
+        Ok(Some(runner)) => named(texts, "discover_ignored", &runner.name),
+        Ok(None) => texts.text("discover_handled"),
+        Err(why) => {
+            log::error!("Failed to update found runner {}: {:?}", user_id, why);
+            println!("[ERROR] Failed to update found runner");
+            texts.text("discover_failed")
+        }
+    };
+
+    let builder = EditInteractionResponse::new()
+        .content(format!("{} ({})", reply, component.user.mention()))
+        .components(Vec::new());
+    if let Err(why) = component.edit_response(&ctx.http, builder).await {
+        log::error!("Failed to update found runner message: {:?}", why);
+        println!("[ERROR] Failed to update found runner message: {:?}", why);
+    }
+}
+
+// Change the language of replies and announcements in the guild, replying in
+// the new language
+async fn set_language(
+    ctx: &Context,
+    guild_id: GuildId,
+    code: Option<&str>,
+    texts: &Locale,
+) -> String {
+    let language = match code.and_then(Language::parse) {
+        Some(language) => language,
+        None => return texts.text("unknown_command"),
+    };
+    let db = get_database(ctx).await;
+    let result = match db.get_guild_settings(guild_id).await {
+        Ok(mut settings) => {
+            settings.language = Some(language);
+            db.save_guild_settings(&settings).await
+        }
+        Err(why) => Err(why),
+    };
+    match result {
+        Ok(()) => {
+            println!("[INFO] Changed language of guild {}", guild_id);
+            let texts = locale(language);
+            texts.message(
+                "language_set",
+                Values::new().set("language", texts.text("language_name")),
+            )
+        }
+        Err(why) => {
+            log::error!("Failed to change language of guild {}: {:?}", guild_id, why);
+            println!("[ERROR] Failed to change language");
+            texts.text("language_failed")
+        }
+    }
+}
+
+// Change the guild's channels and roles given as options, then show all of its settings
+async fn update_settings(
+    ctx: &Context,
+    guild_id: GuildId,
+    options: &[ResolvedOption<'_>],
+    texts: &Locale,
+) -> String {
+    let db = get_database(ctx).await;
+    let mut settings = match db.get_guild_settings(guild_id).await {
+        Ok(settings) => settings,
+        Err(why) => {
+            log::error!("Failed to get settings of guild {}: {:?}", guild_id, why);
+            println!("[ERROR] Failed to get settings of guild {}", guild_id);
+            return texts.text("settings_failed");
+        }
+    };
+    for option in options {
+        match (option.name, &option.value) {
+            ("runs", ResolvedValue::Channel(channel)) => settings.runs_channel = Some(channel.id),
+            ("streams", ResolvedValue::Channel(channel)) => {
+                settings.streams_channel = Some(channel.id)
+            }
+            ("records", ResolvedValue::Channel(channel)) => {
+                settings.records_channel = Some(channel.id)
+            }
+            ("admin_role", ResolvedValue::Role(role)) => settings.admin_role = Some(role.id),
+            ("records_role", ResolvedValue::Role(role)) => settings.records_role = Some(role.id),
+            ("clear", ResolvedValue::String("runs")) => settings.runs_channel = None,
+            ("clear", ResolvedValue::String("streams")) => settings.streams_channel = None,
+            ("clear", ResolvedValue::String("records")) => settings.records_channel = None,
+            ("clear", ResolvedValue::String("admin_role")) => settings.admin_role = None,
+            ("clear", ResolvedValue::String("records_role")) => settings.records_role = None,
+            _ => {}
+        }
+    }
+    if !options.is_empty() {
+        if let Err(why) = db.save_guild_settings(&settings).await {
+            log::error!("Failed to save settings of guild {}: {:?}", guild_id, why);
+            println!("[ERROR] Failed to save settings of guild {}", guild_id);
+            return texts.text("settings_failed");
+        }
+        println!("[INFO] Changed settings of guild {}", guild_id);
+    }
+
+    let not_set = texts.text("not_set");
+    let channel = |id: Option<ChannelId>| id.map_or(not_set.clone(), |id| id.mention().to_string());
+    let role = |id: Option<RoleId>| id.map_or(not_set.clone(), |id| id.mention().to_string());
+    texts.message(
+        "settings",
+        Values::new()
+            .set("runs", channel(settings.runs_channel))
+            .set("streams", channel(settings.streams_channel))
+            .set("records", channel(settings.records_channel))
+            .set("admin_role", role(settings.admin_role))
+            .set("records_role", role(settings.records_role))
+            .set("language", texts.text("language_name")),
+    )
+}
+
+// Show a member tracked by the guild, changing their links first when given any
+async fn edit_member(
+    ctx: &Context,
+    guild_id: GuildId,
+    options: &[ResolvedOption<'_>],
+    texts: &Locale,
+) -> String {
+    let name = match string_option(options, "name") {
+        Some(name) => name,
+        None => return texts.text("unknown_command"),
+    };
+    let db = get_database(ctx).await;
+    let found = match db.get_guild_members(guild_id).await {
+        Ok(members) => members
+            .into_iter()
+            .find(|member| member.name.eq_ignore_ascii_case(name)),
+        Err(why) => {
+            log::error!("Failed to get members of guild {}: {:?}", guild_id, why);
+            println!("[ERROR] Failed to get members of guild {}", guild_id);
+            return texts.text("member_failed");
+        }
+    };
+    let mut member = match found {
+        Some(member) => member,
+        None => return named(texts, "member_not_found", name),
+    };
+
+    let mut changed = false;
+    for option in options {
+        match (option.name, &option.value) {
+            ("runner", ResolvedValue::String(runner)) => {
+                let runners = match db.get_guild_runners(guild_id).await {
+                    Ok(runners) => runners,
+                    Err(why) => {
+                        log::error!("Couldn't get runners: {:?}", why);
+                        return texts.text("member_failed");
+                    }
+                };
+                match runners
+                    .into_iter()
+                    .find(|r| r.name.eq_ignore_ascii_case(runner))
+                {
+                    Some(runner) => {
+                        member.runner_id = Some(runner.id);
+                        member.runner = Some(runner.name);
+                    }
+                    None => return named(texts, "runner_not_tracked", runner),
+                }
+            }
+            ("streamer", ResolvedValue::String(streamer)) => {
+                match find_streamer(&db, guild_id, streamer).await {
+                    Ok(Some(streamer)) => {
+                        member.streamer_id = Some(streamer.streamer_id);
+                        member.streamer = Some(streamer.streamer);
+                    }
+                    Ok(None) => return named(texts, "streamer_not_tracked", streamer),
+                    Err(why) => {
+                        log::error!("Couldn't get streamers: {:?}", why);
+                        return texts.text("member_failed");
+                    }
+                }
+            }
+            ("discord", ResolvedValue::User(user, _)) => member.discord_id = Some(user.id),
+            ("youtube", ResolvedValue::String(youtube)) => match youtube_channel(youtube) {
+                Some(url) => member.youtube = Some(url),
+                None => return texts.text("member_youtube_invalid"),
+            },
+            ("clear", ResolvedValue::String("runner")) => {
+                member.runner_id = None;
+                member.runner = None;
+            }
+            ("clear", ResolvedValue::String("streamer")) => {
+                member.streamer_id = None;
+                member.streamer = None;
+            }
+            ("clear", ResolvedValue::String("discord")) => member.discord_id = None,
+            ("clear", ResolvedValue::String("youtube")) => member.youtube = None,
+            _ => continue,
+        }
+        changed = true;
+    }
+
+    if changed {
+        if let Err(why) = db.save_member(&member).await {
+            log::error!("Failed to save member {}: {:?}", member.name, why);
+            println!("[ERROR] Failed to save member {}", member.name);
+            return texts.text("member_failed");
+        }
+        println!("[INFO] Changed member {}", member.name);
+        if member.runner_id.is_none() && member.streamer_id.is_none() {
+            return named(texts, "member_removed", &member.name);
+        }
+    }
+
+    let not_set = texts.text("not_set");
+    let discord = member
+        .discord_id
+        .map_or(not_set.clone(), |id| id.mention().to_string());
+    texts.message(
+        "member",
+        Values::new()
+            .set("name", &member.name)
+            .set("runner", member.runner.unwrap_or_else(|| not_set.clone()))
+            .set(
+                "streamer",
+                member.streamer.unwrap_or_else(|| not_set.clone()),
+            )
+            .set("discord", discord)
+            .set("youtube", member.youtube.unwrap_or(not_set)),
+    )
+}
+
+// Channel URL from a channel link, @handle or channel id
+fn youtube_channel(input: &str) -> Option<String> {
+    static CHANNEL: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(
+            r"^(?:(?:https?://)?(?:www\.|m\.)?youtube\.com/)?(@[\w.-]+|(?:channel|c|user)/[\w.-]+|UC[\w-]{22})/?$",
+        )
+        .expect("Invalid YouTube channel regex")
+    });
+    let path = CHANNEL.captures(input)?.get(1)?.as_str();
+    if path.starts_with("UC") {
+        Some(format!("https://www.youtube.com/channel/{}", path))
+    } else {
+        Some(format!("https://www.youtube.com/{}", path))
+    }
+}
+
+async fn edit_response(
+    ctx: &Context,
+    command: &CommandInteraction,
+    builder: EditInteractionResponse,
+) {
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        log::error!("Failed to respond to command: {:?}", why);
+        println!("[ERROR] Failed to respond to command: {:?}", why);
+    }
+}
+
+// Suggest tracked names for the option currently being typed
+pub async fn autocomplete(ctx: &Context, command: &CommandInteraction) {
+    let (focused, typed) = match command.data.autocomplete() {
+        Some(option) => (option.name, option.value.to_lowercase()),
+        None => return,
+    };
+
+    let guild_id = match command.guild_id {
+        Some(guild_id) => guild_id,
+        None => return,
+    };
+
+    let names: Vec<String> = {
+        let db = get_database(ctx).await;
+        match (command.data.name.as_str(), focused) {
+            ("member", "name") => db
+                .get_guild_members(guild_id)
+                .await
+                .map(|members| members.into_iter().map(|m| m.name).collect()),
+            ("runner", _) | ("member", "runner") => db
+                .get_guild_runners(guild_id)
+                .await
+                .map(|runners| runners.into_iter().map(|r| r.name).collect()),
+            ("streamer", _) | ("filter", _) | ("member", "streamer") => db
+                .get_guild_streamers(guild_id)
+                .await
+                .map(|streamers| streamers.into_iter().map(|s| s.streamer).collect()),
+            _ => Ok(Vec::new()),
+        }
+        .unwrap_or_default()
+    };
+
+    let choices = names
+        .into_iter()
+        .filter(|name| name.to_lowercase().contains(&typed))
+        .take(MAX_CHOICES)
+        .fold(CreateAutocompleteResponse::new(), |response, name| {
+            response.add_string_choice(name.clone(), name)
+        });
+
+    if let Err(why) = command
+        .create_response(&ctx.http, CreateInteractionResponse::Autocomplete(choices))
+        .await
+    {
+        log::error!("Failed to send autocomplete: {:?}", why);
+        println!("[ERROR] Failed to send autocomplete: {:?}", why);
+    }
+}
+
+// Message about a runner, streamer or member
+fn named(texts: &Locale, key: &str, name: &str) -> String {
+    texts.message(key, Values::new().set("name", name))
+}
+
+// Members who can manage the guild and members with its admin role manage the bot
+async fn is_admin(ctx: &Context, guild_id: GuildId, member: Option<&Member>) -> bool {
+    let member = match member {
+        Some(member) => member,
+        None => return false,
+    };
+    if member
+        .permissions
+        .is_some_and(|permissions| permissions.manage_guild())
+    {
+        return true;
+    }
+    let db = get_database(ctx).await;
+    match db.get_guild_settings(guild_id).await {
+        Ok(settings) => settings
+            .admin_role
+            .is_some_and(|role| member.roles.contains(&role)),
+        Err(why) => {
+            log::error!("Failed to get settings of guild {}: {:?}", guild_id, why);
+            println!("[ERROR] Failed to get settings of guild {}", guild_id);
+            false
+        }
+    }
+}
+
+// Get the subcommand name and its options
+fn subcommand<'a>(
+    options: &'a [ResolvedOption<'a>],
+) -> Option<(&'a str, &'a [ResolvedOption<'a>])> {
+    let option = options.first()?;
+    match &option.value {
+        ResolvedValue::SubCommand(sub_options) => Some((option.name, sub_options)),
+        _ => None,
+    }
+}
+
+fn string_option<'a>(options: &'a [ResolvedOption<'a>], name: &str) -> Option<&'a str> {
+    options.iter().find_map(|o| match o.value {
+        ResolvedValue::String(value) if o.name == name => Some(value.trim()),
+        _ => None,
+    })
+}
+
+fn integer_option(options: &[ResolvedOption], name: &str) -> Option<i64> {
+    options.iter().find_map(|o| match o.value {
+        ResolvedValue::Integer(value) if o.name == name => Some(value),
+        _ => None,
+    })
+}
+
+// Build one page of the tracked runners or streamers embed with its navigation buttons
+async fn list_page(
+    ctx: &Context,
+    guild_id: GuildId,
+    kind: &str,
+    page: usize,
+    texts: &Locale,
+) -> (CreateEmbed, Vec<CreateActionRow>) {
+    let offset = page * PAGE_SIZE;
+    let entries: anyhow::Result<(usize, Vec<String>)> = {
+        let db = get_database(ctx).await;
+        if kind == "runner" {
+            match (
+                db.count_runners(guild_id).await,
+                db.get_runners_page(guild_id, offset, Some(PAGE_SIZE)).await,
+            ) {
+                (Ok(total), Ok(runners)) => Ok((
+                    total,
+                    runners
+                        .into_iter()
+                        .map(|r| {
+                            let last_run = if r.last_run.is_empty() {
+                                texts.text("list_no_runs")
+                            } else {
+                                format!("`{}`", r.last_run)
+                            };
+                            format!("**{}** — {}", r.name, last_run)
+                        })
+                        .collect(),
+                )),
+                (Err(why), _) | (_, Err(why)) => Err(why),
+            }
+        } else {
+            match (
+                db.count_streamers(guild_id).await,
+                db.get_streamers_page(guild_id, offset, Some(PAGE_SIZE))
+                    .await,
+            ) {
+                (Ok(total), Ok(streamers)) => Ok((
+                    total,
+                    streamers
+                        .into_iter()
+                        .map(|s| format!("**{}** — `{}`", s.streamer, s.streamer_id))
+                        .collect(),
+                )),
+                (Err(why), _) | (_, Err(why)) => Err(why),
+            }
+        }
+    };
+
+    let title = if kind == "runner" {
+        texts.text("list_runners")
+    } else {
+        texts.text("list_streamers")
+    };
+    let (total, lines) = match entries {
+        Ok(entries) => entries,
+        Err(why) => {
+            log::error!("Failed to list {}s: {:?}", kind, why);
+            println!("[ERROR] Failed to list {}s", kind);
+            let embed = CreateEmbed::new()
+                .title(title)
+                .description(texts.text("list_failed"))
+                .color(Colour::RED);
+            return (embed, Vec::new());
+        }
+    };
+
+    let pages = total.div_ceil(PAGE_SIZE).max(1);
+    let description = if lines.is_empty() {
+        texts.text("list_empty")
+    } else {
+        lines.join("\n")
+    };
+    let embed = CreateEmbed::new()
+        .title(title)
+        .description(description)
+        .footer(CreateEmbedFooter::new(
+            texts.message(
+                "list_footer",
+                Values::new()
+                    .set("page", page + 1)
+                    .set("pages", pages)
+                    .set("total", total),
+            ),
+        ));
+
+    let buttons = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("list:{}:{}", kind, page.saturating_sub(1)))
+            .label(texts.text("list_previous"))
+            .style(ButtonStyle::Secondary)
+            .disabled(page == 0),
+        CreateButton::new(format!("list:{}:{}", kind, page + 1))
+            .label(texts.text("list_next"))
+            .style(ButtonStyle::Secondary)
+            .disabled(page + 1 >= pages),
+    ])];
+
+    (embed, buttons)
+}
+
+// Track a runner in the guild, replying with an error if they couldn't be
+async fn add_runner(
+    ctx: &Context,
+    guild_id: GuildId,
+    runner: &str,
+    added_by: UserId,
+    texts: &Locale,
+) -> Result<String, String> {
+    // Runners are tracked by user id, so renaming on speedrun.com doesn't break it
+    let user: User = match get_user_by_name(runner).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(named(texts, "runner_not_found", runner)),
+        Err(why) => {
+            log::error!("Failed to look up runner {}: {:?}", runner, why);
+            println!("[ERROR] Failed to look up runner");
+            return Err(named(texts, "runner_not_found", runner));
+        }
+    };
+    add_user(
+        ctx,
+        guild_id,
+        &user.id,
+        &user.names.international,
+        added_by,
+        texts,
+    )
+    .await
+}
+
+// Track a runner in the guild by their speedrun.com user id
+async fn add_user(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: &str,
+    runner: &str,
+    added_by: UserId,
+    texts: &Locale,
+) -> Result<String, String> {
+    // Current PBs are remembered so only the ones after this get announced
+    let runs: Vec<Run> = match get_personal_bests(user_id).await {
+        Ok(runs) => runs,
+        Err(why) => {
+            log::error!("Failed to get personal bests for {}: {:?}", runner, why);
+            println!("[ERROR] Failed to get personal bests");
+            return Err(named(texts, "runner_not_found", runner));
+        }
+    };
+    if runs.is_empty() {
+        println!("[INFO] Runner has no runs");
+    }
+    let run_id: String = latest_run(&runs)
+        .map(|run| run.run.id.clone())
+        .unwrap_or_default();
+    let run_ids: Vec<String> = runs.iter().map(|run| run.run.id.clone()).collect();
+
+    let db = get_database(ctx).await;
+    match db
+        .add_runner(guild_id, runner, user_id, &run_id, &run_ids, added_by)
+        .await
+    {
+        Ok(true) => {
+            println!("[INFO] Added new runner");
+            Ok(named(texts, "runner_added", runner))
+        }
+        Ok(false) => Ok(named(texts, "runner_exists", runner)),
+        Err(why) => {
+            log::error!("Failed to add runner {} {}: {:?}", runner, run_id, why);
+            println!("[ERROR] Failed to add runner");
+            Err(named(texts, "runner_add_failed", runner))
+        }
+    }
+}
+
+async fn remove_runner(ctx: &Context, guild_id: GuildId, runner: &str, texts: &Locale) -> String {
+    let db = get_database(ctx).await;
+    match db.remove_runner(guild_id, runner).await {
+        Ok(true) => {
+            println!("[INFO] Removed runner");
+            named(texts, "runner_removed", runner)
+        }
+        Ok(false) => named(texts, "runner_not_tracked", runner),
+        Err(why) => {
+            log::error!("Failed to remove runner {}: {:?}", runner, why);
+            println!("[ERROR] Failed to remove runner");
+            named(texts, "runner_remove_failed", runner)
+        }
+    }
+}
+
+async fn add_streamer(
+    ctx: &Context,
+    guild_id: GuildId,
+    streamer: &str,
+    added_by: UserId,
+    texts: &Locale,
+) -> String {
+    let streamer_id: String = match get_twitch_user_id(streamer).await {
+        Ok(Some(twitch_user)) => twitch_user.id,
+        Ok(None) => return named(texts, "streamer_not_found", streamer),
+        Err(why) => {
+            log::error!("Failed to get Twitch user id for {}: {:?}", streamer, why);
+            println!("[ERROR] Failed to get Twitch user id");
+            return named(texts, "streamer_lookup_failed", streamer);
+        }
+    };
+
+    let db = get_database(ctx).await;
+    match db
+        .add_streamer(guild_id, streamer, &streamer_id, added_by)
+        .await
+    {
+        Ok(true) => {
+            println!("[INFO] Added new streamer");
+            sync_eventsub(db);
+            named(texts, "streamer_added", streamer)
+        }
+        Ok(false) => named(texts, "streamer_exists", streamer),
+        Err(why) => {
+            log::error!(
+                "Failed to add streamer {} {}: {:?}",
+                streamer,
+                streamer_id,
+                why
+            );
+            println!("[ERROR] Failed to add streamer");
+            named(texts, "streamer_add_failed", streamer)
+        }
+    }
+}
+
+async fn remove_streamer(
+    ctx: &Context,
+    guild_id: GuildId,
+    streamer: &str,
+    texts: &Locale,
+) -> String {
+    let db = get_database(ctx).await;
+    match db.remove_streamer(guild_id, streamer).await {
+        Ok(true) => {
+            println!("[INFO] Removed streamer");
+            sync_eventsub(db);
+            named(texts, "streamer_removed", streamer)
+        }
+        Ok(false) => named(texts, "streamer_not_tracked", streamer),
+        Err(why) => {
+            log::error!("Failed to remove streamer {}: {:?}", streamer, why);
+            println!("[ERROR] Failed to remove streamer");
+            named(texts, "streamer_remove_failed", streamer)
+        }
+    }
+}
+
+// Update EventSub subscriptions right away instead of waiting for the next sync
+fn sync_eventsub(db: Arc<Database>) {
+    if !config::get_config().eventsub.enabled {
+        return;
+    }
+    tokio::spawn(async move {
+        if let Err(why) = eventsub::sync_subscriptions(&db).await {
+            log::error!("Failed to sync EventSub subscriptions: {:?}", why);
+            println!("[ERROR] Failed to sync EventSub subscriptions: {:?}", why);
+        }
+    });
+}
+
+// Find a streamer tracked by the guild by name
+async fn find_streamer(
+    db: &Database,
+    guild_id: GuildId,
+    name: &str,
+) -> anyhow::Result<Option<Streamer>> {
+    Ok(db
+        .get_guild_streamers(guild_id)
+        .await?
+        .into_iter()
+        .find(|s| s.streamer.eq_ignore_ascii_case(name)))
+}
+
+async fn add_filter(
+    ctx: &Context,
+    guild_id: GuildId,
+    options: &[ResolvedOption<'_>],
+    added_by: UserId,
+    texts: &Locale,
+) -> String {
+    let kind = match string_option(options, "kind").and_then(FilterKind::parse) {
+        Some(kind) => kind,
+        None => return texts.text("filter_unknown_kind"),
+    };
+    let value = string_option(options, "value").unwrap_or_default();
+    if let Err(why) = filters::validate(kind, value) {
+        return texts.message("filter_invalid", Values::new().set("reason", why));
+    }
+
+    let db = get_database(ctx).await;
+    let streamer = match string_option(options, "streamer") {
+        Some(name) => match find_streamer(&db, guild_id, name).await {
+            Ok(Some(streamer)) => Some(streamer),
+            Ok(None) => return named(texts, "streamer_not_tracked", name),
+            Err(why) => {
+                log::error!("Couldn't get streamers: {:?}", why);
+                return texts.text("filter_add_failed");
+            }
+        },
+        None => None,
+    };
+
+    let streamer_id = streamer.as_ref().map(|s| s.streamer_id.as_str());
+    let mut values = Values::new();
+    values.set("kind", kind.as_str()).set("value", value).set(
+        "streamer",
+        streamer.as_ref().map_or("", |s| s.streamer.as_str()),
+    );
+    match db
+        .add_stream_filter(guild_id, streamer_id, kind, value, added_by)
+        .await
+    {
+        Ok(true) => {
+            println!("[INFO] Added stream filter");
+            texts.message("filter_added", &values)
+        }
+        Ok(false) => texts.message("filter_exists", &values),
+        Err(why) => {
+            log::error!("Failed to add stream filter: {:?}", why);
+            println!("[ERROR] Failed to add stream filter");
+            texts.text("filter_add_failed")
+        }
+    }
+}
+
+async fn remove_filter(
+    ctx: &Context,
+    guild_id: GuildId,
+    options: &[ResolvedOption<'_>],
+    texts: &Locale,
+) -> String {
+    let id = match integer_option(options, "id") {
+        Some(id) => id,
+        None => return texts.text("filter_missing_id"),
+    };
+    let db = get_database(ctx).await;
+    match db.remove_stream_filter(guild_id, id).await {
+        Ok(true) => {
+            println!("[INFO] Removed stream filter");
+            texts.message("filter_removed", Values::new().set("id", id))
+        }
+        Ok(false) => texts.message("filter_not_found", Values::new().set("id", id)),
+        Err(why) => {
+            log::error!("Failed to remove stream filter {}: {:?}", id, why);
+            println!("[ERROR] Failed to remove stream filter");
+            texts.text("filter_remove_failed")
+        }
+    }
+}
+
+async fn list_filters(
+    ctx: &Context,
+    guild_id: GuildId,
+    options: &[ResolvedOption<'_>],
+    texts: &Locale,
+) -> String {
+    let db = get_database(ctx).await;
+    let streamers = match db.get_guild_streamers(guild_id).await {
+        Ok(streamers) => streamers,
+        Err(why) => {
+            log::error!("Couldn't get streamers: {:?}", why);
+            return texts.text("filter_list_failed");
+        }
+    };
+    let streamer_id = match string_option(options, "streamer") {
+        Some(name) => match streamers
+            .iter()
+            .find(|s| s.streamer.eq_ignore_ascii_case(name))
+        {
+            Some(streamer) => Some(streamer.streamer_id.as_str()),
+            None => return named(texts, "streamer_not_tracked", name),
+        },
+        None => None,
+    };
+
+    let rules = match db.get_stream_filters(guild_id, streamer_id).await {
+        Ok(rules) => rules,
+        Err(why) => {
+            log::error!("Failed to get stream filters: {:?}", why);
+            return texts.text("filter_list_failed");
+        }
+    };
+    if rules.is_empty() {
+        return texts.text("filter_none");
+    }
+
+    let lines: Vec<String> = rules
+        .iter()
+        .map(|rule| {
+            let scope = match &rule.streamer_id {
+                Some(id) => streamers
+                    .iter()
+                    .find(|s| &s.streamer_id == id)
+                    .map_or(id.clone(), |s| s.streamer.clone()),
+                None => texts.text("filter_everyone"),
+            };
+            format!(
+                "#{} `{}` `{}` — {}",
+                rule.id,
+                rule.kind.as_str(),
+                rule.value,
+                scope
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
@@ -1,12 +1,309 @@
+use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
 use serde::Deserialize;
+use serenity::model::id::{ChannelId, RoleId};
 use std::fs;
+use std::net::SocketAddr;
+use tokio::time::Duration;
+
+use crate::locale::{self, Language};
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub discord_token: String,
     pub twitch_client_id: String,
-    pub twitch_oauth: String,
+    pub twitch_client_secret: String,
+    // Initial settings of the guild the channels are in, every guild can change
+    // its own with /settings
+    pub channels: Option<Channels>,
+    pub roles: Option<Roles>,
+    // Language of guilds that didn't pick one with /language
+    #[serde(default)]
+    pub language: Language,
+    #[serde(default)]
+    pub discovery: Discovery,
+    #[serde(default)]
+    pub api: Api,
+    #[serde(default)]
+    pub cache: Cache,
+    #[serde(default)]
+    pub records: Records,
+    #[serde(default)]
+    pub polling: Polling,
+    #[serde(default)]
+    pub streams: Streams,
+    #[serde(default)]
+    pub eventsub: EventSub,
+    // Overrides of the message catalogues in locales/
+    #[serde(default)]
+    pub templates: toml::Table,
+}
+
+// Discord channels the bot posts into and listens on
+#[derive(Deserialize, Debug)]
+pub struct Channels {
+    pub runs: ChannelId,
+    pub streams: ChannelId,
+}
+
+// Discord roles allowed to manage the bot
+#[derive(Deserialize, Debug)]
+pub struct Roles {
+    pub admin: RoleId,
+}
+
+// Looking for new runners among the players of recently verified runs
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Discovery {
+    pub enabled: bool,
+    // Admin channel where found runners are proposed
+    pub channel: Option<ChannelId>,
+    // speedrun.com country codes like "cz" or region codes like "us/ca"
+    pub countries: Vec<String>,
+    // Seconds between searches
+    pub interval: u64,
+    // Pages of 200 recent runs checked per search
+    pub pages: usize,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            enabled: false,
+            channel: None,
+            countries: vec![String::from("cz"), String::from("sk")],
+            interval: 3600,
+            pages: 5,
+        }
+    }
+}
+
+impl Discovery {
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+}
+
+// Limits shared by every speedrun.com and Twitch request
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Api {
+    // Sustained request rate across all APIs
+    pub requests_per_minute: u32,
+    // Requests allowed in a burst before the rate applies
+    pub burst: u32,
+    // Seconds before a request is abandoned
+    pub timeout: u64,
+    // Retries of rate limited or failed requests before giving up
+    pub max_retries: u32,
+}
+
+impl Default for Api {
+    fn default() -> Self {
+        Api {
+            requests_per_minute: 60,
+            burst: 10,
+            timeout: 30,
+            max_retries: 5,
+        }
+    }
+}
+
+impl Api {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+// World and national records get their own announcement
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Records {
+    // Initial channel and pinged role of the guild in [channels]
+    pub channel: Option<ChannelId>,
+    pub role: Option<RoleId>,
+    // speedrun.com country codes whose national records are announced
+    pub countries: Vec<String>,
+}
+
+impl Default for Records {
+    fn default() -> Self {
+        Records {
+            channel: None,
+            role: None,
+            countries: vec![String::from("cz"), String::from("sk")],
+        }
+    }
+}
+
+// speedrun.com games, categories, levels and variables kept between requests
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Cache {
+    // Seconds before cached metadata is fetched again
+    pub metadata_ttl: u64,
+    // Responses kept in memory, the rest are read from the database
+    pub size: usize,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            metadata_ttl: 86400,
+            size: 500,
+        }
+    }
+}
+
+// Delays between polling cycles, in seconds
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Polling {
+    // Pause between passes over all runners
+    pub runs_interval: u64,
+    pub streams_interval: u64,
+    // Refresh viewers and uptime of live stream embeds, 0 to only edit on changes
+    pub stream_refresh_interval: u64,
+    // Refresh runner names from speedrun.com, 0 to never refresh
+    pub names_interval: u64,
+}
+
+impl Default for Polling {
+    fn default() -> Self {
+        Polling {
+            runs_interval: 10,
+            streams_interval: 30,
+            stream_refresh_interval: 600,
+            names_interval: 86400,
+        }
+    }
+}
+
+impl Polling {
+    pub fn runs_interval(&self) -> Duration {
+        Duration::from_secs(self.runs_interval)
+    }
+
+    pub fn streams_interval(&self) -> Duration {
+        Duration::from_secs(self.streams_interval)
+    }
+
+    pub fn stream_refresh_interval(&self) -> Option<Duration> {
+        match self.stream_refresh_interval {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+
+    pub fn names_interval(&self) -> Option<Duration> {
+        match self.names_interval {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+}
+
+// What happens to live stream announcements once the stream ends
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Streams {
+    pub ended: StreamEnded,
+    // Save finished streams in the database for statistics
+    pub record_sessions: bool,
+}
+
+impl Default for Streams {
+    fn default() -> Self {
+        Streams {
+            ended: StreamEnded::Delete,
+            record_sessions: false,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamEnded {
+    // Delete the announcement
+    Delete,
+    // Edit the announcement into a summary of the stream
+    Summary,
+}
+
+// Twitch EventSub webhooks, used instead of polling streams when enabled
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct EventSub {
+    pub enabled: bool,
+    // Public HTTPS URL that Twitch sends notifications to
+    pub callback_url: String,
+    // Local address the webhook server listens on
+    pub listen: SocketAddr,
+    // Shared secret used to sign notifications, 10 to 100 characters
+    pub secret: String,
+}
+
+impl Default for EventSub {
+    fn default() -> Self {
+        EventSub {
+            enabled: false,
+            callback_url: String::new(),
+            listen: SocketAddr::from(([0, 0, 0, 0], 8080)),
+            secret: String::new(),
+        }
+    }
+}
+
+impl Config {
+    // Check values that parse fine but would break the bot at runtime
+    pub fn validate(&self) -> Result<()> {
+        if self.discord_token.trim().is_empty() {
+            bail!("discord_token must not be empty");
+        }
+        if self.twitch_client_id.trim().is_empty() {
+            bail!("twitch_client_id must not be empty");
+        }
+        if self.twitch_client_secret.trim().is_empty() {
+            bail!("twitch_client_secret must not be empty");
+        }
+        if self.discovery.enabled {
+            if self.discovery.channel.is_none() {
+                bail!("discovery.channel must be set when discovery is enabled");
+            }
+            if self.discovery.interval == 0 {
+                bail!("discovery.interval must be at least 1 second");
+            }
+        }
+        if self.api.requests_per_minute == 0 {
+            bail!("api.requests_per_minute must be at least 1");
+        }
+        if self.api.burst == 0 {
+            bail!("api.burst must be at least 1");
+        }
+        if self.api.timeout == 0 {
+            bail!("api.timeout must be at least 1 second");
+        }
+        if self.cache.size == 0 {
+            bail!("cache.size must be at least 1");
+        }
+        if self.polling.runs_interval == 0 {
+            bail!("polling.runs_interval must be at least 1 second");
+        }
+        if self.polling.streams_interval == 0 {
+            bail!("polling.streams_interval must be at least 1 second");
+        }
+        if self.eventsub.enabled {
+            if !self.eventsub.callback_url.starts_with("https://") {
+                bail!("eventsub.callback_url must be an https:// URL");
+            }
+            if !(10..=100).contains(&self.eventsub.secret.len()) {
+                bail!("eventsub.secret must be between 10 and 100 characters");
+            }
+        }
+        locale::load_all(&self.templates)?;
+        Ok(())
+    }
 }
 
 pub static CONFIG: Lazy<Config> = Lazy::new(|| {
@@ -1,63 +1,525 @@
-use anyhow::Result;
-use rusqlite::{params, Connection};
+use anyhow::{bail, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serenity::model::id::{ChannelId, GuildId, MessageId, RoleId, UserId};
 use serenity::prelude::TypeMapKey;
 use serenity::prelude::*;
+use std::collections::HashSet;
+use std::fs;
 use std::path::Path;
+use std::sync::Arc;
+
+use crate::filters::FilterKind;
+use crate::locale::Language;
 
 pub struct Database {
     conn: Mutex<Connection>,
 }
 
 impl TypeMapKey for Database {
-    type Value = Database;
+    type Value = Arc<Database>;
+}
+
+// Get a shared handle to the database, the TypeMap lock is released right away
+pub async fn get_database(ctx: &Context) -> Arc<Database> {
+    let data = ctx.data.read().await;
+    Arc::clone(
+        data.get::<Database>()
+            .expect("Database is not in the TypeMap"),
+    )
 }
 
+const DATABASE_PATH: &str = "runners.db";
+
+// Schema migrations, applied in order. The database's user_version holds the
+// number of migrations already applied, so only ever append to this list.
+const MIGRATIONS: &[&str] = &[
+    // 1: Original schema
+    "CREATE TABLE IF NOT EXISTS runners (runner TEXT, last_run TEXT);
+    CREATE TABLE IF NOT EXISTS streamers (streamer TEXT, streamerId TEXT);",
+    // 2: Primary keys, unique names and ids, who added each entry and when.
+    // Duplicates keep their oldest row, streamers without a Twitch id are dropped
+    // as they could never be polled.
+    "CREATE TABLE runners_new (
+        id INTEGER PRIMARY KEY,
+        runner TEXT NOT NULL UNIQUE COLLATE NOCASE,
+        last_run TEXT NOT NULL DEFAULT '',
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT
+    );
+    INSERT OR IGNORE INTO runners_new (runner, last_run)
+        SELECT runner, COALESCE(last_run, '') FROM runners
+        WHERE runner IS NOT NULL AND runner != '' ORDER BY rowid;
+    DROP TABLE runners;
+    ALTER TABLE runners_new RENAME TO runners;
+
+    CREATE TABLE streamers_new (
+        id INTEGER PRIMARY KEY,
+        streamer TEXT NOT NULL COLLATE NOCASE,
+        streamerId TEXT NOT NULL UNIQUE,
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT
+    );
+    INSERT OR IGNORE INTO streamers_new (streamer, streamerId)
+        SELECT streamer, streamerId FROM streamers
+        WHERE streamer IS NOT NULL AND streamerId IS NOT NULL AND streamerId != ''
+        ORDER BY rowid;
+    DROP TABLE streamers;
+    ALTER TABLE streamers_new RENAME TO streamers;",
+    // 3: Live stream announcements, so they survive restarts
+    "CREATE TABLE stream_announcements (
+        streamer_id TEXT PRIMARY KEY,
+        stream_id TEXT NOT NULL,
+        channel_id TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        posted_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );",
+    // 4: Stream details for ended summaries, finished streams for statistics
+    "ALTER TABLE stream_announcements ADD COLUMN user_name TEXT NOT NULL DEFAULT '';
+    ALTER TABLE stream_announcements ADD COLUMN title TEXT NOT NULL DEFAULT '';
+    ALTER TABLE stream_announcements ADD COLUMN started_at TEXT NOT NULL DEFAULT '';
+    ALTER TABLE stream_announcements ADD COLUMN games TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE stream_announcements ADD COLUMN peak_viewers INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE stream_sessions (
+        id INTEGER PRIMARY KEY,
+        streamer_id TEXT NOT NULL,
+        stream_id TEXT NOT NULL UNIQUE,
+        user_name TEXT NOT NULL,
+        title TEXT NOT NULL,
+        games TEXT NOT NULL,
+        peak_viewers INTEGER NOT NULL,
+        started_at TEXT NOT NULL,
+        ended_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        vod_url TEXT
+    );",
+    // 5: Rules deciding which streams get announced, streamer_id '' for global rules
+    "CREATE TABLE stream_filters (
+        id INTEGER PRIMARY KEY,
+        streamer_id TEXT NOT NULL DEFAULT '',
+        kind TEXT NOT NULL,
+        value TEXT NOT NULL,
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT,
+        UNIQUE (streamer_id, kind, value)
+    );",
+    // 6: Cached speedrun.com metadata responses, fetched_at in unix seconds
+    "CREATE TABLE api_cache (
+        url TEXT PRIMARY KEY,
+        body TEXT NOT NULL,
+        fetched_at INTEGER NOT NULL
+    );",
+    // 7: Every personal best already posted per runner. Existing runners only
+    // know their latest run, so their other PBs are remembered on the first check.
+    "CREATE TABLE announced_runs (
+        runner_id INTEGER NOT NULL,
+        run_id TEXT NOT NULL,
+        announced_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (runner_id, run_id)
+    );
+    ALTER TABLE runners ADD COLUMN runs_seeded INTEGER NOT NULL DEFAULT 0;",
+    // 8: Runners found on speedrun.com, status is 'pending', 'approved' or 'ignored'
+    "CREATE TABLE discovered_runners (
+        user_id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        country TEXT NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending',
+        discovered_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        decided_by TEXT
+    );",
+    // 9: Settings picked per guild with slash commands
+    "CREATE TABLE guild_settings (
+        guild_id TEXT PRIMARY KEY,
+        language TEXT
+    );",
+    // 10: Channels, roles, tracked runners and streamers, announcements and filters
+    // per guild. Runners and streamers stay shared so each is polled once. Rows
+    // from before get the guild of the configured channels on the next start,
+    // until then guild_id is ''.
+    "ALTER TABLE guild_settings ADD COLUMN runs_channel TEXT;
+    ALTER TABLE guild_settings ADD COLUMN streams_channel TEXT;
+    ALTER TABLE guild_settings ADD COLUMN records_channel TEXT;
+    ALTER TABLE guild_settings ADD COLUMN admin_role TEXT;
+    ALTER TABLE guild_settings ADD COLUMN records_role TEXT;
+
+    CREATE TABLE guild_runners (
+        guild_id TEXT NOT NULL,
+        runner_id INTEGER NOT NULL,
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT,
+        PRIMARY KEY (guild_id, runner_id)
+    );
+    CREATE TABLE guild_streamers (
+        guild_id TEXT NOT NULL,
+        streamer_id TEXT NOT NULL,
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT,
+        PRIMARY KEY (guild_id, streamer_id)
+    );
+
+    CREATE TABLE stream_announcements_new (
+        guild_id TEXT NOT NULL DEFAULT '',
+        streamer_id TEXT NOT NULL,
+        stream_id TEXT NOT NULL,
+        channel_id TEXT NOT NULL,
+        message_id TEXT NOT NULL,
+        posted_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        user_name TEXT NOT NULL DEFAULT '',
+        title TEXT NOT NULL DEFAULT '',
+        started_at TEXT NOT NULL DEFAULT '',
+        games TEXT NOT NULL DEFAULT '[]',
+        peak_viewers INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (guild_id, streamer_id)
+    );
+    INSERT INTO stream_announcements_new (streamer_id, stream_id, channel_id, message_id,
+        posted_at, user_name, title, started_at, games, peak_viewers)
+        SELECT streamer_id, stream_id, channel_id, message_id, posted_at, user_name, title,
+        started_at, games, peak_viewers FROM stream_announcements;
+    DROP TABLE stream_announcements;
+    ALTER TABLE stream_announcements_new RENAME TO stream_announcements;
+
+    CREATE TABLE stream_filters_new (
+        id INTEGER PRIMARY KEY,
+        guild_id TEXT NOT NULL DEFAULT '',
+        streamer_id TEXT NOT NULL DEFAULT '',
+        kind TEXT NOT NULL,
+        value TEXT NOT NULL,
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT,
+        UNIQUE (guild_id, streamer_id, kind, value)
+    );
+    INSERT INTO stream_filters_new (id, streamer_id, kind, value, added_at, added_by)
+        SELECT id, streamer_id, kind, value, added_at, added_by FROM stream_filters;
+    DROP TABLE stream_filters;
+    ALTER TABLE stream_filters_new RENAME TO stream_filters;",
+    // 11: Profiles linking the runner, streamer, Discord account and YouTube
+    // channel of one person. Runners and streamers with the same name become
+    // one member.
+    "CREATE TABLE members (
+        id INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+        discord_id TEXT UNIQUE,
+        runner_id INTEGER UNIQUE,
+        streamer_id TEXT UNIQUE,
+        youtube TEXT,
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT
+    );
+    INSERT INTO members (name, runner_id, added_at, added_by)
+        SELECT runner, id, added_at, added_by FROM runners ORDER BY id;
+    INSERT INTO members (name, streamer_id, added_at, added_by)
+        SELECT streamer, streamerId, added_at, added_by FROM streamers WHERE true ORDER BY id
+        ON CONFLICT (name) DO UPDATE SET streamer_id = excluded.streamer_id
+        WHERE streamer_id IS NULL;",
+    // 12: speedrun.com user ids, which stay the same when runners rename.
+    // Runners approved from discovery get theirs now, the rest when next checked.
+    "ALTER TABLE runners ADD COLUMN user_id TEXT;
+    UPDATE runners SET user_id = (SELECT user_id FROM discovered_runners
+        WHERE name = runners.runner COLLATE NOCASE AND status = 'approved'
+        ORDER BY discovered_at DESC LIMIT 1);
+    CREATE UNIQUE INDEX runners_user_id ON runners (user_id);",
+    // 13: When a stream was last seen live, its end for streams that went
+    // offline while the bot was down
+    "ALTER TABLE stream_announcements ADD COLUMN last_seen_at TEXT NOT NULL DEFAULT '';",
+    // 14: Only runs verified after this are announced, so old runs that become
+    // a PB again once a newer one is removed aren't. Tracked runners start now
+    "ALTER TABLE runners ADD COLUMN runs_since TEXT NOT NULL DEFAULT '';
+    UPDATE runners SET runs_since = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');",
+    // 15: Whether the guild got the settings from the config, which happens once
+    // so settings cleared with /settings stay cleared. Guilds with any setting
+    // already got them
+    "ALTER TABLE guild_settings ADD COLUMN config_adopted INTEGER NOT NULL DEFAULT 0;
+    UPDATE guild_settings SET config_adopted = 1 WHERE runs_channel IS NOT NULL
+        OR streams_channel IS NOT NULL OR records_channel IS NOT NULL
+        OR admin_role IS NOT NULL OR records_role IS NOT NULL;",
+    // 16: Members belong to one guild, linking only what that guild tracks, so
+    // admins can only change the members of their own guild. Members of guilds
+    // set up on the next start are made again then
+    "CREATE TABLE members_new (
+        id INTEGER PRIMARY KEY,
+        guild_id TEXT NOT NULL,
+        name TEXT NOT NULL COLLATE NOCASE,
+        discord_id TEXT,
+        runner_id INTEGER,
+        streamer_id TEXT,
+        youtube TEXT,
+        added_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        added_by TEXT,
+        UNIQUE (guild_id, name),
+        UNIQUE (guild_id, discord_id),
+        UNIQUE (guild_id, runner_id),
+        UNIQUE (guild_id, streamer_id)
+    );
+    INSERT INTO members_new (guild_id, name, discord_id, runner_id, streamer_id, youtube,
+        added_at, added_by)
+        SELECT DISTINCT guilds.guild_id, name, discord_id,
+            CASE WHEN EXISTS (SELECT 1 FROM guild_runners
+                WHERE guild_runners.guild_id = guilds.guild_id
+                AND guild_runners.runner_id = members.runner_id) THEN members.runner_id END,
+            CASE WHEN EXISTS (SELECT 1 FROM guild_streamers
+                WHERE guild_streamers.guild_id = guilds.guild_id
+                AND guild_streamers.streamer_id = members.streamer_id)
+                THEN members.streamer_id END,
+            youtube, members.added_at, members.added_by
+        FROM members JOIN (SELECT guild_id, runner_id, NULL AS streamer_id FROM guild_runners
+            UNION SELECT guild_id, NULL, streamer_id FROM guild_streamers) AS guilds
+        ON guilds.runner_id = members.runner_id OR guilds.streamer_id = members.streamer_id
+        ORDER BY members.id;
+    DROP TABLE members;
+    ALTER TABLE members_new RENAME TO members;",
+];
+
+// Members left without a runner or a streamer have nothing to link
+const DELETE_EMPTY_MEMBERS: &str =
+    "DELETE FROM members WHERE runner_id IS NULL AND streamer_id IS NULL";
+
 // Connect to the sqlite3 database
 pub fn connect() -> Result<Database> {
-    let conn = Connection::open(Path::new("runners.db"))?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS runners (runner TEXT, last_run TEXT)",
-        [],
-    )?;
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS streamers (streamer TEXT, streamerId TEXT)",
-        [],
-    )?;
+    let path = Path::new(DATABASE_PATH);
+    let existed = path.exists();
+    let mut conn = Connection::open(path)?;
+    migrate(&mut conn, existed)?;
     let db = Database {
         conn: Mutex::new(conn),
     };
     Ok(db)
 }
 
+// Empty database with the current schema
+#[cfg(test)]
+pub fn connect_in_memory() -> Database {
+    let mut conn = Connection::open_in_memory().expect("Failed to open database");
+    migrate(&mut conn, false).expect("Failed to migrate database");
+    Database {
+        conn: Mutex::new(conn),
+    }
+}
+
+// Bring the schema up to date, backing up existing databases first
+fn migrate(conn: &mut Connection, existed: bool) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        bail!(
+            "Database schema version {} is newer than the supported version {}",
+            version,
+            MIGRATIONS.len()
+        );
+    }
+    if version == MIGRATIONS.len() {
+        return Ok(());
+    }
+
+    if existed {
+        let backup = format!("{}.v{}.bak", DATABASE_PATH, version);
+        fs::copy(DATABASE_PATH, &backup)?;
+        println!("[INFO] Backed up database to {}", backup);
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+        println!("[INFO] Migrated database to version {}", index + 1);
+    }
+    Ok(())
+}
+
 impl Database {
-    // Add a new runner
-    pub async fn add_runner(&self, runner: &str, last_run: &str) -> Result<()> {
-        let conn = &self.conn.lock().await;
-        conn.execute(
-            "INSERT INTO runners VALUES (?1, ?2)",
-            params![runner, last_run],
+    // Track a runner in a guild. Runners new to every guild are added along with
+    // their current PBs so those aren't announced. They're linked to their member
+    // in the guild. Returns false if the guild already tracks them
+    pub async fn add_runner(
+        &self,
+        guild_id: GuildId,
+        runner: &str,
+        user_id: &str,
+        last_run: &str,
+        run_ids: &[String],
+        added_by: UserId,
+    ) -> Result<bool> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        // Someone else had the name before renaming on speedrun.com. User ids are
+        // what counts, so they go by theirs until their new name is looked up
+        tx.execute(
+            "UPDATE OR IGNORE members SET name =
+                (SELECT user_id FROM runners WHERE runners.id = members.runner_id)
+                WHERE name = ?1 AND runner_id IN
+                (SELECT id FROM runners WHERE runner = ?1 AND user_id != ?2)",
+            params![runner, user_id],
+        )?;
+        tx.execute(
+            "UPDATE runners SET runner = user_id WHERE runner = ?1 AND user_id != ?2",
+            params![runner, user_id],
+        )?;
+        let added = tx.execute(
+            "INSERT OR IGNORE INTO runners
+                (runner, user_id, last_run, added_by, runs_seeded, runs_since)
+                VALUES (?1, ?2, ?3, ?4, 1, strftime('%Y-%m-%dT%H:%M:%SZ', 'now'))",
+            params![runner, user_id, last_run, added_by.to_string()],
         )?;
+        // Already known under this name without an id, or under an old name
+        tx.execute(
+            "UPDATE OR IGNORE runners SET user_id = ?2 WHERE runner = ?1 AND user_id IS NULL",
+            params![runner, user_id],
+        )?;
+        tx.execute(
+            "UPDATE OR IGNORE runners SET runner = ?1 WHERE user_id = ?2",
+            params![runner, user_id],
+        )?;
+        let runner_id: i64 = tx.query_row(
+            "SELECT id FROM runners WHERE user_id = ?1",
+            params![user_id],
+            |row| row.get(0),
+        )?;
+        if added > 0 {
+            for run_id in run_ids {
+                tx.execute(
+                    "INSERT OR IGNORE INTO announced_runs (runner_id, run_id) VALUES (?1, ?2)",
+                    params![runner_id, run_id],
+                )?;
+            }
+        }
+        let linked = tx.execute(
+            "INSERT OR IGNORE INTO guild_runners (guild_id, runner_id, added_by)
+                VALUES (?1, ?2, ?3)",
+            params![guild_id.to_string(), runner_id, added_by.to_string()],
+        )?;
+        // Part of the guild's member with the same name, or a new member of their own
+        if linked > 0 {
+            tx.execute(
+                "INSERT INTO members (guild_id, name, runner_id, added_by) SELECT ?1, ?2, ?3, ?4
+                    WHERE NOT EXISTS (SELECT 1 FROM members WHERE guild_id = ?1 AND runner_id = ?3)
+                    ON CONFLICT (guild_id, name) DO UPDATE SET runner_id = excluded.runner_id
+                    WHERE runner_id IS NULL",
+                params![
+                    guild_id.to_string(),
+                    runner,
+                    runner_id,
+                    added_by.to_string()
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(linked > 0)
+    }
+
+    // Store the speedrun.com user id and current name of a runner, renaming the
+    // members named after them too
+    pub async fn set_runner_user(&self, runner_id: i64, user_id: &str, name: &str) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "UPDATE OR IGNORE members SET name = ?1 WHERE runner_id = ?2
+                AND name = (SELECT runner FROM runners WHERE id = ?2)",
+            params![name, runner_id],
+        )?;
+        tx.execute(
+            "UPDATE runners SET user_id = ?1, runner = ?2 WHERE id = ?3",
+            params![user_id, name, runner_id],
+        )?;
+        tx.commit()?;
         Ok(())
     }
 
-    // Update runner's last run
-    pub async fn update_runner(&self, runner: String, last_run: String) -> Result<()> {
-        let conn = &self.conn.lock().await;
-        conn.execute(
-            "UPDATE runners SET last_run = ?1 WHERE runner = ?2",
-            params![last_run, runner],
+    // Remember an announced run, also making it the runner's last run and only
+    // announcing runs verified after it from now on
+    pub async fn add_announced_run(
+        &self,
+        runner_id: i64,
+        run_id: &str,
+        verified_at: &str,
+    ) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO announced_runs (runner_id, run_id) VALUES (?1, ?2)",
+            params![runner_id, run_id],
+        )?;
+        tx.execute(
+            "UPDATE runners SET last_run = ?1, runs_since = ?2 WHERE id = ?3",
+            params![run_id, verified_at, runner_id],
         )?;
+        tx.commit()?;
         Ok(())
     }
 
-    // Get all runners
+    // Remember runs of a runner without announcing them, only announcing runs
+    // verified from now on
+    pub async fn seed_announced_runs(&self, runner_id: i64, run_ids: &[String]) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        for run_id in run_ids {
+            tx.execute(
+                "INSERT OR IGNORE INTO announced_runs (runner_id, run_id) VALUES (?1, ?2)",
+                params![runner_id, run_id],
+            )?;
+        }
+        tx.execute(
+            "UPDATE runners SET runs_seeded = 1,
+                runs_since = strftime('%Y-%m-%dT%H:%M:%SZ', 'now') WHERE id = ?1",
+            params![runner_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Get the ids of all runs already announced for a runner
+    pub async fn get_announced_runs(&self, runner_id: i64) -> Result<HashSet<String>> {
+        let conn = &self.conn.lock().await;
+        let mut statement =
+            conn.prepare("SELECT run_id FROM announced_runs WHERE runner_id = ?1")?;
+        let run_ids = statement.query_map(params![runner_id], |row| row.get(0))?;
+        Ok(run_ids.collect::<rusqlite::Result<HashSet<String>>>()?)
+    }
+
+    // Stop tracking a runner in a guild, unlinking them from its member. They and
+    // their announced runs are dropped once no guild tracks them. Returns false if
+    // the guild didn't track them
+    pub async fn remove_runner(&self, guild_id: GuildId, runner: &str) -> Result<bool> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        let removed = tx.execute(
+            "DELETE FROM guild_runners WHERE guild_id = ?1 AND runner_id IN
+                (SELECT id FROM runners WHERE runner = ?2)",
+            params![guild_id.to_string(), runner],
+        )?;
+        tx.execute(
+            "DELETE FROM announced_runs WHERE runner_id IN
+                (SELECT id FROM runners WHERE runner = ?1
+                AND id NOT IN (SELECT runner_id FROM guild_runners))",
+            params![runner],
+        )?;
+        tx.execute(
+            "DELETE FROM runners WHERE runner = ?1
+                AND id NOT IN (SELECT runner_id FROM guild_runners)",
+            params![runner],
+        )?;
+        tx.execute(
+            "UPDATE members SET runner_id = NULL WHERE guild_id = ?1
+                AND runner_id NOT IN (SELECT runner_id FROM guild_runners WHERE guild_id = ?1)",
+            params![guild_id.to_string()],
+        )?;
+        tx.execute(DELETE_EMPTY_MEMBERS, [])?;
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    // Get all runners tracked by at least one guild
     pub async fn get_runners(&self) -> Result<Vec<Runner>> {
         let conn = &self.conn.lock().await;
-        let mut statement = conn.prepare("SELECT * FROM runners")?;
+        let mut statement = conn.prepare(
+            "SELECT id, runner, last_run, runs_seeded, user_id, runs_since FROM runners
+                WHERE id IN (SELECT runner_id FROM guild_runners)",
+        )?;
         let runners = statement.query_map([], |row| {
             Ok(Runner {
-                name: row.get(0)?,
-                last_run: row.get(1)?,
+                id: row.get(0)?,
+                name: row.get(1)?,
+                last_run: row.get(2)?,
+                runs_seeded: row.get(3)?,
+                user_id: row.get(4)?,
+                runs_since: row.get(5)?,
             })
         })?;
         let mut runners_vector: Vec<Runner> = Vec::new();
@@ -67,20 +529,139 @@ impl Database {
         Ok(runners_vector)
     }
 
-    // Add a new streamer
-    pub async fn add_streamer(&self, streamer: &str, streamer_id: &str) -> Result<()> {
+    // Get the runners tracked by a guild ordered by name
+    pub async fn get_guild_runners(&self, guild_id: GuildId) -> Result<Vec<Runner>> {
+        self.get_runners_page(guild_id, 0, None).await
+    }
+
+    // Get one page of the runners tracked by a guild ordered by name, all of
+    // them without a limit
+    pub async fn get_runners_page(
+        &self,
+        guild_id: GuildId,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<Runner>> {
         let conn = &self.conn.lock().await;
-        conn.execute(
-            "INSERT INTO streamers VALUES (?1, ?2)",
-            params![streamer, streamer_id],
+        let mut statement = conn.prepare(
+            "SELECT id, runner, last_run, runs_seeded, user_id, runs_since FROM runners
+                JOIN guild_runners ON runner_id = id WHERE guild_id = ?1
+                ORDER BY runner LIMIT ?2 OFFSET ?3",
         )?;
-        Ok(())
+        // A negative limit means no limit to SQLite
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let params = params![guild_id.to_string(), limit, offset as i64];
+        let runners = statement.query_map(params, |row| {
+            Ok(Runner {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                last_run: row.get(2)?,
+                runs_seeded: row.get(3)?,
+                user_id: row.get(4)?,
+                runs_since: row.get(5)?,
+            })
+        })?;
+        Ok(runners.collect::<rusqlite::Result<Vec<Runner>>>()?)
+    }
+
+    // Get the number of runners tracked by a guild
+    pub async fn count_runners(&self, guild_id: GuildId) -> Result<usize> {
+        let conn = &self.conn.lock().await;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM guild_runners WHERE guild_id = ?1",
+            params![guild_id.to_string()],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
     }
 
-    // Get all streamers
+    // Get the settings of every guild tracking a runner
+    pub async fn get_runner_guilds(&self, runner_id: i64) -> Result<Vec<GuildSettings>> {
+        let conn = &self.conn.lock().await;
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM guild_runners LEFT JOIN guild_settings USING (guild_id)
+                WHERE runner_id = ?1",
+            GUILD_SETTINGS_COLUMNS
+        ))?;
+        let mut rows = statement.query(params![runner_id])?;
+        let mut guilds = Vec::new();
+        while let Some(row) = rows.next()? {
+            guilds.push(guild_settings(row)?);
+        }
+        Ok(guilds)
+    }
+
+    // Track a streamer in a guild, linked to their member in the guild. Returns
+    // false if the guild already tracks them
+    pub async fn add_streamer(
+        &self,
+        guild_id: GuildId,
+        streamer: &str,
+        streamer_id: &str,
+        added_by: UserId,
+    ) -> Result<bool> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO streamers (streamer, streamerId, added_by) VALUES (?1, ?2, ?3)",
+            params![streamer, streamer_id, added_by.to_string()],
+        )?;
+        let linked = tx.execute(
+            "INSERT OR IGNORE INTO guild_streamers (guild_id, streamer_id, added_by)
+                VALUES (?1, ?2, ?3)",
+            params![guild_id.to_string(), streamer_id, added_by.to_string()],
+        )?;
+        if linked > 0 {
+            tx.execute(
+                "INSERT INTO members (guild_id, name, streamer_id, added_by) SELECT ?1, ?2, ?3, ?4
+                    WHERE NOT EXISTS
+                    (SELECT 1 FROM members WHERE guild_id = ?1 AND streamer_id = ?3)
+                    ON CONFLICT (guild_id, name) DO UPDATE SET streamer_id = excluded.streamer_id
+                    WHERE streamer_id IS NULL",
+                params![
+                    guild_id.to_string(),
+                    streamer,
+                    streamer_id,
+                    added_by.to_string()
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(linked > 0)
+    }
+
+    // Stop tracking a streamer in a guild, unlinking them from its member. They're
+    // dropped once no guild tracks them. Returns false if the guild didn't track them
+    pub async fn remove_streamer(&self, guild_id: GuildId, streamer: &str) -> Result<bool> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        let removed = tx.execute(
+            "DELETE FROM guild_streamers WHERE guild_id = ?1 AND streamer_id IN
+                (SELECT streamerId FROM streamers WHERE streamer = ?2)",
+            params![guild_id.to_string(), streamer],
+        )?;
+        tx.execute(
+            "DELETE FROM streamers WHERE streamer = ?1
+                AND streamerId NOT IN (SELECT streamer_id FROM guild_streamers)",
+            params![streamer],
+        )?;
+        tx.execute(
+            "UPDATE members SET streamer_id = NULL WHERE guild_id = ?1 AND streamer_id NOT IN
+                (SELECT streamer_id FROM guild_streamers WHERE guild_id = ?1)",
+            params![guild_id.to_string()],
+        )?;
+        tx.execute(DELETE_EMPTY_MEMBERS, [])?;
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    // Get all streamers tracked by at least one guild
     pub async fn get_streamers(&self) -> Result<Vec<Streamer>> {
         let conn = &self.conn.lock().await;
-        let mut statement = conn.prepare("SELECT * FROM streamers")?;
+        let mut statement = conn.prepare(
+            "SELECT streamer, streamerId FROM streamers
+                WHERE streamerId IN (SELECT streamer_id FROM guild_streamers)",
+        )?;
         let streamers = statement.query_map([], |row| {
             Ok(Streamer {
                 streamer: row.get(0)?,
@@ -93,12 +674,559 @@ impl Database {
         }
         Ok(streamers_vector)
     }
+
+    // Get the streamers tracked by a guild ordered by name
+    pub async fn get_guild_streamers(&self, guild_id: GuildId) -> Result<Vec<Streamer>> {
+        self.get_streamers_page(guild_id, 0, None).await
+    }
+
+    // Get one page of the streamers tracked by a guild ordered by name, all of
+    // them without a limit
+    pub async fn get_streamers_page(
+        &self,
+        guild_id: GuildId,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<Vec<Streamer>> {
+        let conn = &self.conn.lock().await;
+        let mut statement = conn.prepare(
+            "SELECT streamer, streamerId FROM streamers
+                JOIN guild_streamers ON streamer_id = streamerId WHERE guild_id = ?1
+                ORDER BY streamer LIMIT ?2 OFFSET ?3",
+        )?;
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let params = params![guild_id.to_string(), limit, offset as i64];
+        let streamers = statement.query_map(params, |row| {
+            Ok(Streamer {
+                streamer: row.get(0)?,
+                streamer_id: row.get(1)?,
+            })
+        })?;
+        Ok(streamers.collect::<rusqlite::Result<Vec<Streamer>>>()?)
+    }
+
+    // Get the settings of every guild tracking a streamer
+    pub async fn get_streamer_guilds(&self, streamer_id: &str) -> Result<Vec<GuildSettings>> {
+        let conn = &self.conn.lock().await;
+        let mut statement = conn.prepare(&format!(
+            "SELECT {} FROM guild_streamers LEFT JOIN guild_settings USING (guild_id)
+                WHERE streamer_id = ?1",
+            GUILD_SETTINGS_COLUMNS
+        ))?;
+        let mut rows = statement.query(params![streamer_id])?;
+        let mut guilds = Vec::new();
+        while let Some(row) = rows.next()? {
+            guilds.push(guild_settings(row)?);
+        }
+        Ok(guilds)
+    }
+
+    // Save the announcement of a live stream, replacing an older one
+    pub async fn add_announcement(&self, announcement: &StreamAnnouncement) -> Result<()> {
+        let conn = &self.conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO stream_announcements
+                (guild_id, streamer_id, stream_id, channel_id, message_id, user_name, title,
+                started_at, games, peak_viewers, last_seen_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                announcement.guild_id.to_string(),
+                announcement.streamer_id,
+                announcement.stream_id,
+                announcement.channel_id.to_string(),
+                announcement.message_id.to_string(),
+                announcement.user_name,
+                announcement.title,
+                announcement.started_at,
+                serde_json::to_string(&announcement.games)?,
+                announcement.peak_viewers as i64,
+                announcement.last_seen_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Forget the announcement of a streamer in a guild
+    pub async fn remove_announcement(&self, guild_id: GuildId, streamer_id: &str) -> Result<()> {
+        let conn = &self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM stream_announcements WHERE guild_id = ?1 AND streamer_id = ?2",
+            params![guild_id.to_string(), streamer_id],
+        )?;
+        Ok(())
+    }
+
+    // Get all saved announcements that belong to a guild
+    pub async fn get_announcements(&self) -> Result<Vec<StreamAnnouncement>> {
+        let conn = &self.conn.lock().await;
+        let mut statement = conn.prepare(
+            "SELECT guild_id, streamer_id, stream_id, channel_id, message_id, user_name, title,
+                started_at, games, peak_viewers, last_seen_at FROM stream_announcements
+                WHERE guild_id != ''",
+        )?;
+        let mut rows = statement.query([])?;
+        let mut announcements_vector: Vec<StreamAnnouncement> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let guild_id: String = row.get(0)?;
+            let channel_id: String = row.get(3)?;
+            let message_id: String = row.get(4)?;
+            let games: String = row.get(8)?;
+            let peak_viewers: i64 = row.get(9)?;
+            announcements_vector.push(StreamAnnouncement {
+                guild_id: GuildId::new(guild_id.parse()?),
+                streamer_id: row.get(1)?,
+                stream_id: row.get(2)?,
+                channel_id: ChannelId::new(channel_id.parse()?),
+                message_id: MessageId::new(message_id.parse()?),
+                user_name: row.get(5)?,
+                title: row.get(6)?,
+                started_at: row.get(7)?,
+                games: serde_json::from_str(&games)?,
+                peak_viewers: peak_viewers as u64,
+                last_seen_at: row.get(10)?,
+            });
+        }
+        Ok(announcements_vector)
+    }
+
+    // Record a finished stream
+    pub async fn add_session(
+        &self,
+        announcement: &StreamAnnouncement,
+        ended_at: &str,
+        vod_url: Option<&str>,
+    ) -> Result<()> {
+        let conn = &self.conn.lock().await;
+        conn.execute(
+            "INSERT OR IGNORE INTO stream_sessions (streamer_id, stream_id, user_name, title,
+                games, peak_viewers, started_at, ended_at, vod_url)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                announcement.streamer_id,
+                announcement.stream_id,
+                announcement.user_name,
+                announcement.title,
+                serde_json::to_string(&announcement.games)?,
+                announcement.peak_viewers as i64,
+                announcement.started_at,
+                ended_at,
+                vod_url,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Add a stream filter rule to a guild, for all its streamers when there's no
+    // streamer id. Returns false if the same rule already exists
+    pub async fn add_stream_filter(
+        &self,
+        guild_id: GuildId,
+        streamer_id: Option<&str>,
+        kind: FilterKind,
+        value: &str,
+        added_by: UserId,
+    ) -> Result<bool> {
+        let conn = &self.conn.lock().await;
+        let added = conn.execute(
+            "INSERT OR IGNORE INTO stream_filters (guild_id, streamer_id, kind, value, added_by)
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                guild_id.to_string(),
+                streamer_id.unwrap_or(""),
+                kind.as_str(),
+                value,
+                added_by.to_string()
+            ],
+        )?;
+        Ok(added > 0)
+    }
+
+    // Remove a stream filter rule of a guild, returns false if it didn't exist
+    pub async fn remove_stream_filter(&self, guild_id: GuildId, id: i64) -> Result<bool> {
+        let conn = &self.conn.lock().await;
+        let removed = conn.execute(
+            "DELETE FROM stream_filters WHERE guild_id = ?1 AND id = ?2",
+            params![guild_id.to_string(), id],
+        )?;
+        Ok(removed > 0)
+    }
+
+    // Get a guild's rules for all streamers and the rules of a streamer, or every
+    // rule of the guild without a streamer id
+    pub async fn get_stream_filters(
+        &self,
+        guild_id: GuildId,
+        streamer_id: Option<&str>,
+    ) -> Result<Vec<StreamFilter>> {
+        let conn = &self.conn.lock().await;
+        let mut statement = conn.prepare(
+            "SELECT id, streamer_id, kind, value FROM stream_filters WHERE guild_id = ?1
+                AND (?2 IS NULL OR streamer_id = '' OR streamer_id = ?2) ORDER BY id",
+        )?;
+        let mut rows = statement.query(params![guild_id.to_string(), streamer_id])?;
+        let mut filters_vector: Vec<StreamFilter> = Vec::new();
+        while let Some(row) = rows.next()? {
+            let streamer_id: String = row.get(1)?;
+            let kind: String = row.get(2)?;
+            let kind = match FilterKind::parse(&kind) {
+                Some(kind) => kind,
+                None => {
+                    log::warn!("Unknown stream filter kind {}", kind);
+                    continue;
+                }
+            };
+            filters_vector.push(StreamFilter {
+                id: row.get(0)?,
+                streamer_id: if streamer_id.is_empty() {
+                    None
+                } else {
+                    Some(streamer_id)
+                },
+                kind,
+                value: row.get(3)?,
+            });
+        }
+        Ok(filters_vector)
+    }
+
+    // Get a cached API response, fresh or not
+    pub async fn get_cached_response(&self, url: &str) -> Result<Option<CachedResponse>> {
+        let conn = &self.conn.lock().await;
+        let cached = conn
+            .query_row(
+                "SELECT body, fetched_at FROM api_cache WHERE url = ?1",
+                params![url],
+                |row| {
+                    Ok(CachedResponse {
+                        body: row.get(0)?,
+                        fetched_at: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(cached)
+    }
+
+    // Save an API response, replacing the previous one
+    pub async fn set_cached_response(&self, url: &str, cached: &CachedResponse) -> Result<()> {
+        let conn = &self.conn.lock().await;
+        conn.execute(
+            "INSERT OR REPLACE INTO api_cache (url, body, fetched_at) VALUES (?1, ?2, ?3)",
+            params![url, cached.body, cached.fetched_at],
+        )?;
+        Ok(())
+    }
+
+    // Remember a runner found on speedrun.com, returns false if they were found before
+    pub async fn add_discovered_runner(
+        &self,
+        user_id: &str,
+        name: &str,
+        country: &str,
+    ) -> Result<bool> {
+        let conn = &self.conn.lock().await;
+        let added = conn.execute(
+            "INSERT OR IGNORE INTO discovered_runners (user_id, name, country) VALUES (?1, ?2, ?3)",
+            params![user_id, name, country],
+        )?;
+        Ok(added > 0)
+    }
+
+    // Forget a found runner so they get proposed again
+    pub async fn remove_discovered_runner(&self, user_id: &str) -> Result<()> {
+        let conn = &self.conn.lock().await;
+        conn.execute(
+            "DELETE FROM discovered_runners WHERE user_id = ?1",
+            params![user_id],
+        )?;
+        Ok(())
+    }
+
+    // Approve or ignore a pending runner, returns None if they aren't pending anymore
+    pub async fn decide_discovered_runner(
+        &self,
+        user_id: &str,
+        approved: bool,
+        decided_by: UserId,
+    ) -> Result<Option<DiscoveredRunner>> {
+        let conn = &self.conn.lock().await;
+        let status = if approved { "approved" } else { "ignored" };
+        let updated = conn.execute(
+            "UPDATE discovered_runners SET status = ?1, decided_by = ?2
+                WHERE user_id = ?3 AND status = 'pending'",
+            params![status, decided_by.to_string(), user_id],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        let runner = conn.query_row(
+            "SELECT user_id, name, country FROM discovered_runners WHERE user_id = ?1",
+            params![user_id],
+            |row| {
+                Ok(DiscoveredRunner {
+                    user_id: row.get(0)?,
+                    name: row.get(1)?,
+                    country: row.get(2)?,
+                })
+            },
+        )?;
+        Ok(Some(runner))
+    }
+
+    // Make a runner pending again after their approval couldn't be carried out
+    pub async fn reopen_discovered_runner(&self, user_id: &str) -> Result<()> {
+        let conn = &self.conn.lock().await;
+        conn.execute(
+            "UPDATE discovered_runners SET status = 'pending', decided_by = NULL
+                WHERE user_id = ?1",
+            params![user_id],
+        )?;
+        Ok(())
+    }
+
+    // Get the member a runner belongs to in a guild
+    pub async fn get_runner_member(
+        &self,
+        guild_id: GuildId,
+        runner_id: i64,
+    ) -> Result<Option<MemberProfile>> {
+        let conn = &self.conn.lock().await;
+        let member = conn
+            .query_row(
+                &format!("{} WHERE guild_id = ?1 AND runner_id = ?2", SELECT_MEMBERS),
+                params![guild_id.to_string(), runner_id],
+                |row| Ok(member_profile(row)),
+            )
+            .optional()?;
+        member.transpose()
+    }
+
+    // Get the members of a guild ordered by name
+    pub async fn get_guild_members(&self, guild_id: GuildId) -> Result<Vec<MemberProfile>> {
+        let conn = &self.conn.lock().await;
+        let mut statement = conn.prepare(&format!(
+            "{} WHERE guild_id = ?1 ORDER BY name",
+            SELECT_MEMBERS
+        ))?;
+        let mut rows = statement.query(params![guild_id.to_string()])?;
+        let mut members = Vec::new();
+        while let Some(row) = rows.next()? {
+            members.push(member_profile(row)?);
+        }
+        Ok(members)
+    }
+
+    // Save the links of a member, taking the runner, streamer and Discord account
+    // away from any other member of the guild. Members left empty are dropped,
+    // this one too
+    pub async fn save_member(&self, member: &MemberProfile) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        let discord_id = member.discord_id.map(|id| id.to_string());
+        tx.execute(
+            "UPDATE members SET
+                runner_id = CASE WHEN runner_id = ?2 THEN NULL ELSE runner_id END,
+                streamer_id = CASE WHEN streamer_id = ?3 THEN NULL ELSE streamer_id END,
+                discord_id = CASE WHEN discord_id = ?4 THEN NULL ELSE discord_id END
+                WHERE id != ?1 AND guild_id = (SELECT guild_id FROM members WHERE id = ?1)",
+            params![member.id, member.runner_id, member.streamer_id, discord_id],
+        )?;
+        tx.execute(
+            "UPDATE members SET runner_id = ?2, streamer_id = ?3, discord_id = ?4, youtube = ?5
+                WHERE id = ?1",
+            params![
+                member.id,
+                member.runner_id,
+                member.streamer_id,
+                discord_id,
+                member.youtube
+            ],
+        )?;
+        tx.execute(DELETE_EMPTY_MEMBERS, [])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Get the settings of a guild, all unset if it has none yet
+    pub async fn get_guild_settings(&self, guild_id: GuildId) -> Result<GuildSettings> {
+        let conn = &self.conn.lock().await;
+        let settings = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM guild_settings WHERE guild_id = ?1",
+                    GUILD_SETTINGS_COLUMNS
+                ),
+                params![guild_id.to_string()],
+                |row| Ok(guild_settings(row)),
+            )
+            .optional()?;
+        match settings {
+            Some(settings) => settings,
+            None => Ok(GuildSettings::new(guild_id)),
+        }
+    }
+
+    pub async fn save_guild_settings(&self, settings: &GuildSettings) -> Result<()> {
+        let conn = &self.conn.lock().await;
+        conn.execute(
+            "INSERT INTO guild_settings (guild_id, language, runs_channel, streams_channel,
+                records_channel, admin_role, records_role) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT (guild_id) DO UPDATE SET language = excluded.language,
+                runs_channel = excluded.runs_channel,
+                streams_channel = excluded.streams_channel,
+                records_channel = excluded.records_channel,
+                admin_role = excluded.admin_role,
+                records_role = excluded.records_role",
+            params![
+                settings.guild_id.to_string(),
+                settings.language.map(|language| language.code()),
+                settings.runs_channel.map(|id| id.to_string()),
+                settings.streams_channel.map(|id| id.to_string()),
+                settings.records_channel.map(|id| id.to_string()),
+                settings.admin_role.map(|id| id.to_string()),
+                settings.records_role.map(|id| id.to_string()),
+            ],
+        )?;
+        Ok(())
+    }
+
+    // Give the guild of the configured channels whatever of their settings it
+    // doesn't have yet, only the first time, along with the runners, streamers,
+    // announcements and filters from before guilds had their own
+    pub async fn adopt_guild(&self, settings: &GuildSettings) -> Result<()> {
+        let mut conn = self.conn.lock().await;
+        let tx = conn.transaction()?;
+        let guild_id = settings.guild_id.to_string();
+        tx.execute(
+            "INSERT INTO guild_settings (guild_id, runs_channel, streams_channel,
+                records_channel, admin_role, records_role, config_adopted)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1)
+                ON CONFLICT (guild_id) DO UPDATE SET
+                runs_channel = COALESCE(runs_channel, excluded.runs_channel),
+                streams_channel = COALESCE(streams_channel, excluded.streams_channel),
+                records_channel = COALESCE(records_channel, excluded.records_channel),
+                admin_role = COALESCE(admin_role, excluded.admin_role),
+                records_role = COALESCE(records_role, excluded.records_role),
+                config_adopted = 1
+                WHERE NOT config_adopted",
+            params![
+                guild_id,
+                settings.runs_channel.map(|id| id.to_string()),
+                settings.streams_channel.map(|id| id.to_string()),
+                settings.records_channel.map(|id| id.to_string()),
+                settings.admin_role.map(|id| id.to_string()),
+                settings.records_role.map(|id| id.to_string()),
+            ],
+        )?;
+        // Members first, named after the runners and streamers no guild tracks yet
+        tx.execute(
+            "INSERT INTO members (guild_id, name, runner_id, added_at, added_by)
+                SELECT ?1, runner, id, added_at, added_by FROM runners
+                WHERE id NOT IN (SELECT runner_id FROM guild_runners) ORDER BY id
+                ON CONFLICT (guild_id, name) DO NOTHING",
+            params![guild_id],
+        )?;
+        tx.execute(
+            "INSERT INTO members (guild_id, name, streamer_id, added_at, added_by)
+                SELECT ?1, streamer, streamerId, added_at, added_by FROM streamers
+                WHERE streamerId NOT IN (SELECT streamer_id FROM guild_streamers) ORDER BY id
+                ON CONFLICT (guild_id, name) DO UPDATE SET streamer_id = excluded.streamer_id
+                WHERE streamer_id IS NULL",
+            params![guild_id],
+        )?;
+        tx.execute(
+            "INSERT INTO guild_runners (guild_id, runner_id, added_at, added_by)
+                SELECT ?1, id, added_at, added_by FROM runners
+                WHERE id NOT IN (SELECT runner_id FROM guild_runners)",
+            params![guild_id],
+        )?;
+        tx.execute(
+            "INSERT INTO guild_streamers (guild_id, streamer_id, added_at, added_by)
+                SELECT ?1, streamerId, added_at, added_by FROM streamers
+                WHERE streamerId NOT IN (SELECT streamer_id FROM guild_streamers)",
+            params![guild_id],
+        )?;
+        tx.execute(
+            "UPDATE stream_announcements SET guild_id = ?1 WHERE guild_id = ''",
+            params![guild_id],
+        )?;
+        tx.execute(
+            "UPDATE stream_filters SET guild_id = ?1 WHERE guild_id = ''",
+            params![guild_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Get the number of streamers tracked by a guild
+    pub async fn count_streamers(&self, guild_id: GuildId) -> Result<usize> {
+        let conn = &self.conn.lock().await;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM guild_streamers WHERE guild_id = ?1",
+            params![guild_id.to_string()],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+}
+
+const GUILD_SETTINGS_COLUMNS: &str = "guild_id, language, runs_channel, streams_channel,
+    records_channel, admin_role, records_role";
+
+// Read a row of GUILD_SETTINGS_COLUMNS, ids that don't parse count as unset
+fn guild_settings(row: &rusqlite::Row) -> Result<GuildSettings> {
+    fn id(row: &rusqlite::Row, index: usize) -> rusqlite::Result<Option<u64>> {
+        let id: Option<String> = row.get(index)?;
+        Ok(id.and_then(|id| id.parse().ok()).filter(|id| *id != 0))
+    }
+    let guild_id: String = row.get(0)?;
+    let language: Option<String> = row.get(1)?;
+    Ok(GuildSettings {
+        guild_id: GuildId::new(guild_id.parse()?),
+        language: language.as_deref().and_then(Language::parse),
+        runs_channel: id(row, 2)?.map(ChannelId::new),
+        streams_channel: id(row, 3)?.map(ChannelId::new),
+        records_channel: id(row, 4)?.map(ChannelId::new),
+        admin_role: id(row, 5)?.map(RoleId::new),
+        records_role: id(row, 6)?.map(RoleId::new),
+    })
+}
+
+const SELECT_MEMBERS: &str = "SELECT members.id, name, discord_id, runner_id, runner,
+    streamer_id, streamer, youtube FROM members
+    LEFT JOIN runners ON runners.id = runner_id
+    LEFT JOIN streamers ON streamerId = streamer_id";
+
+// Read a row of SELECT_MEMBERS
+fn member_profile(row: &rusqlite::Row) -> Result<MemberProfile> {
+    let discord_id: Option<String> = row.get(2)?;
+    Ok(MemberProfile {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        discord_id: discord_id
+            .and_then(|id| id.parse().ok())
+            .filter(|id| *id != 0)
+            .map(UserId::new),
+        runner_id: row.get(3)?,
+        runner: row.get(4)?,
+        streamer_id: row.get(5)?,
+        streamer: row.get(6)?,
+        youtube: row.get(7)?,
+    })
 }
 
 #[derive(Debug)]
 pub struct Runner {
+    pub id: i64,
     pub name: String,
     pub last_run: String,
+    // Whether all current PBs are in announced_runs
+    pub runs_seeded: bool,
+    // speedrun.com user id, None for runners from before ids were stored until
+    // they're next checked
+    pub user_id: Option<String>,
+    // RFC 3339, runs verified before this aren't announced. Empty for none
+    pub runs_since: String,
 }
 
 #[derive(Debug)]
@@ -106,3 +1234,340 @@ pub struct Streamer {
     pub streamer: String,
     pub streamer_id: String,
 }
+
+// One person with their speedrun.com and Twitch accounts in a guild, managed
+// with /member
+#[derive(Debug, Clone)]
+pub struct MemberProfile {
+    pub id: i64,
+    pub name: String,
+    pub discord_id: Option<UserId>,
+    // Tracked runner, with their speedrun.com name
+    pub runner_id: Option<i64>,
+    pub runner: Option<String>,
+    // Twitch id of the tracked streamer, with their login name
+    pub streamer_id: Option<String>,
+    pub streamer: Option<String>,
+    // Channel URL
+    pub youtube: Option<String>,
+}
+
+// Settings a guild picks with /settings and /language
+#[derive(Debug, Clone)]
+pub struct GuildSettings {
+    pub guild_id: GuildId,
+    // The configured language when not set
+    pub language: Option<Language>,
+    // New PBs aren't posted in the guild without a channel for them
+    pub runs_channel: Option<ChannelId>,
+    // Live streams aren't posted in the guild without a channel for them
+    pub streams_channel: Option<ChannelId>,
+    // World and national records, the runs channel when not set
+    pub records_channel: Option<ChannelId>,
+    // Allowed to manage the bot, besides members who can manage the guild
+    pub admin_role: Option<RoleId>,
+    // Pinged when a record is posted
+    pub records_role: Option<RoleId>,
+}
+
+impl GuildSettings {
+    pub fn new(guild_id: GuildId) -> Self {
+        GuildSettings {
+            guild_id,
+            language: None,
+            runs_channel: None,
+            streams_channel: None,
+            records_channel: None,
+            admin_role: None,
+            records_role: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamAnnouncement {
+    pub guild_id: GuildId,
+    pub streamer_id: String,
+    pub stream_id: String,
+    pub channel_id: ChannelId,
+    pub message_id: MessageId,
+    pub user_name: String,
+    pub title: String,
+    pub started_at: String,
+    // Every game played during the stream, in order
+    pub games: Vec<String>,
+    pub peak_viewers: u64,
+    // RFC 3339, empty for announcements saved before it was
+    pub last_seen_at: String,
+}
+
+#[derive(Debug)]
+pub struct DiscoveredRunner {
+    pub user_id: String,
+    pub name: String,
+    pub country: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub body: String,
+    // Unix timestamp in seconds
+    pub fetched_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamFilter {
+    pub id: i64,
+    // None for rules applying to every streamer
+    pub streamer_id: Option<String>,
+    pub kind: FilterKind,
+    pub value: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(conn: &Connection, sql: &str) -> Vec<(String, String)> {
+        let mut statement = conn.prepare(sql).unwrap();
+        let rows = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    fn pair(a: &str, b: &str) -> (String, String) {
+        (a.to_string(), b.to_string())
+    }
+
+    #[test]
+    fn migrations_keep_data_of_the_original_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE runners (runner TEXT, last_run TEXT);
+            CREATE TABLE streamers (streamer TEXT, streamerId TEXT);
+            INSERT INTO runners VALUES ('Bob', 'a'), ('bob', 'b'), (NULL, 'c'), ('', 'd'),
+                ('Ann', NULL);
+            INSERT INTO streamers VALUES ('x', '1'), ('x2', '1'), ('y', ''), ('z', NULL),
+                (NULL, '3'), ('w', '4');",
+        )
+        .unwrap();
+        migrate(&mut conn, false).unwrap();
+
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+
+        // Duplicates keep their oldest row, runners without a name are dropped
+        assert_eq!(
+            pairs(&conn, "SELECT runner, last_run FROM runners ORDER BY id"),
+            [pair("Bob", "a"), pair("Ann", "")]
+        );
+        // Streamers need a name and a Twitch id, the first of each id stays
+        assert_eq!(
+            pairs(
+                &conn,
+                "SELECT streamer, streamerId FROM streamers ORDER BY id"
+            ),
+            [pair("x", "1"), pair("w", "4")]
+        );
+
+        // Nothing is left to apply on the next start
+        migrate(&mut conn, false).unwrap();
+        assert_eq!(
+            pairs(&conn, "SELECT runner, last_run FROM runners ORDER BY id").len(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn config_settings_are_adopted_once() {
+        let db = connect_in_memory();
+        let guild_id = GuildId::new(1);
+        let mut config = GuildSettings::new(guild_id);
+        config.runs_channel = Some(ChannelId::new(2));
+        config.admin_role = Some(RoleId::new(3));
+        db.adopt_guild(&config).await.unwrap();
+
+        let mut settings = db.get_guild_settings(guild_id).await.unwrap();
+        assert_eq!(settings.admin_role, Some(RoleId::new(3)));
+        settings.admin_role = None;
+        db.save_guild_settings(&settings).await.unwrap();
+
+        // Cleared settings stay cleared on the next start
+        db.adopt_guild(&config).await.unwrap();
+        let settings = db.get_guild_settings(guild_id).await.unwrap();
+        assert_eq!(settings.runs_channel, Some(ChannelId::new(2)));
+        assert_eq!(settings.admin_role, None);
+    }
+
+    #[tokio::test]
+    async fn members_belong_to_one_guild() {
+        let db = connect_in_memory();
+        let (first, second) = (GuildId::new(1), GuildId::new(2));
+        let user = UserId::new(3);
+        for guild_id in [first, second] {
+            db.add_runner(guild_id, "Ann", "ann1", "", &[], user)
+                .await
+                .unwrap();
+            db.add_streamer(guild_id, "ann", "10", user).await.unwrap();
+        }
+
+        let mut member = db.get_guild_members(first).await.unwrap().remove(0);
+        assert_eq!(member.streamer_id.as_deref(), Some("10"));
+        member.discord_id = Some(UserId::new(4));
+        member.streamer_id = None;
+        db.save_member(&member).await.unwrap();
+
+        let other = db.get_guild_members(second).await.unwrap().remove(0);
+        assert_eq!(other.discord_id, None);
+        assert_eq!(other.streamer_id.as_deref(), Some("10"));
+
+        // Removing the runner in one guild keeps them in the other
+        db.remove_runner(first, "Ann").await.unwrap();
+        assert!(db.get_guild_members(first).await.unwrap().is_empty());
+        let runner_id = other.runner_id.unwrap();
+        let member = db.get_runner_member(second, runner_id).await.unwrap();
+        assert_eq!(member.map(|member| member.id), Some(other.id));
+    }
+
+    #[tokio::test]
+    async fn found_runners_are_decided_once() {
+        let db = connect_in_memory();
+        let admin = UserId::new(3);
+        let status = |db: &Database| {
+            let conn = db.conn.try_lock().unwrap();
+            pairs(&conn, "SELECT user_id, status FROM discovered_runners")
+        };
+        assert!(db.add_discovered_runner("ann1", "Ann", "cz").await.unwrap());
+        assert!(!db.add_discovered_runner("ann1", "Ann", "cz").await.unwrap());
+
+        let runner = db
+            .decide_discovered_runner("ann1", true, admin)
+            .await
+            .unwrap();
+        assert_eq!(runner.map(|runner| runner.name), Some(String::from("Ann")));
+        assert_eq!(status(&db), [pair("ann1", "approved")]);
+        // Already decided, by another admin clicking at the same time
+        assert!(db
+            .decide_discovered_runner("ann1", false, admin)
+            .await
+            .unwrap()
+            .is_none());
+
+        // Adding them failed, so they can be decided again
+        db.reopen_discovered_runner("ann1").await.unwrap();
+        assert_eq!(status(&db), [pair("ann1", "pending")]);
+
+        let runner = db
+            .decide_discovered_runner("ann1", false, admin)
+            .await
+            .unwrap();
+        assert!(runner.is_some());
+        assert_eq!(status(&db), [pair("ann1", "ignored")]);
+        assert!(db
+            .decide_discovered_runner("ann1", true, admin)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(db
+            .decide_discovered_runner("bob1", true, admin)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    fn runners(conn: &Connection) -> Vec<(String, String)> {
+        pairs(
+            conn,
+            "SELECT runner, COALESCE(user_id, '') FROM runners ORDER BY id",
+        )
+    }
+
+    #[tokio::test]
+    async fn runners_are_added_by_user_id() {
+        let db = connect_in_memory();
+        let (first, second) = (GuildId::new(1), GuildId::new(2));
+        let user = UserId::new(3);
+        let runs = [String::from("run1")];
+        assert!(db
+            .add_runner(first, "Ann", "ann1", "run1", &runs, user)
+            .await
+            .unwrap());
+        assert!(!db
+            .add_runner(first, "ann", "ann1", "", &[], user)
+            .await
+            .unwrap());
+        // Other guilds share the runner and the runs already announced
+        assert!(db
+            .add_runner(second, "Ann", "ann1", "", &[], user)
+            .await
+            .unwrap());
+        let runner_id = db.get_runners().await.unwrap()[0].id;
+        let announced = db.get_announced_runs(runner_id).await.unwrap();
+        assert_eq!(announced, HashSet::from([String::from("run1")]));
+
+        // A runner from before user ids were stored is found by name
+        db.conn
+            .lock()
+            .await
+            .execute("INSERT INTO runners (runner) VALUES ('Bob')", [])
+            .unwrap();
+        db.add_runner(first, "Bob", "bob1", "", &[], user)
+            .await
+            .unwrap();
+        // A renamed runner keeps their row
+        db.add_runner(first, "Anna", "ann1", "", &[], user)
+            .await
+            .unwrap();
+        let conn = db.conn.lock().await;
+        assert_eq!(runners(&conn), [pair("Anna", "ann1"), pair("Bob", "bob1")]);
+    }
+
+    #[tokio::test]
+    async fn names_taken_over_by_someone_else_are_freed() {
+        let db = connect_in_memory();
+        let guild_id = GuildId::new(1);
+        let user = UserId::new(3);
+        db.add_runner(guild_id, "X", "a1", "", &[], user)
+            .await
+            .unwrap();
+
+        // a1 renamed on speedrun.com and b1 took their old name
+        assert!(db
+            .add_runner(guild_id, "X", "b1", "", &[], user)
+            .await
+            .unwrap());
+        {
+            let conn = db.conn.lock().await;
+            assert_eq!(runners(&conn), [pair("a1", "a1"), pair("X", "b1")]);
+        }
+        let members = db.get_guild_members(guild_id).await.unwrap();
+        let names: Vec<&str> = members.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["a1", "X"]);
+
+        // Looking up the new name renames the runner and their member
+        let runner_id = members[0].runner_id.unwrap();
+        db.set_runner_user(runner_id, "a1", "Y").await.unwrap();
+        let member = db.get_runner_member(guild_id, runner_id).await.unwrap();
+        assert_eq!(member.map(|member| member.name), Some(String::from("Y")));
+    }
+
+    #[test]
+    fn approved_runners_get_their_user_id() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..11] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 11).unwrap();
+        conn.execute_batch(
+            "INSERT INTO runners (runner) VALUES ('Ann'), ('Bob');
+            INSERT INTO discovered_runners (user_id, name, country, status)
+                VALUES ('ann1', 'ann', 'cz', 'approved'), ('bob1', 'Bob', 'cz', 'ignored');",
+        )
+        .unwrap();
+        migrate(&mut conn, false).unwrap();
+        assert_eq!(runners(&conn), [pair("Ann", "ann1"), pair("Bob", "")]);
+    }
+}
@@ -0,0 +1,142 @@
+use serenity::{
+    all::{ButtonStyle, ChannelId, CreateActionRow, CreateButton, CreateEmbed, CreateMessage},
+    prelude::*,
+};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::time::sleep;
+
+use crate::apirequests::*;
+use crate::apitypes::*;
+use crate::config::get_config;
+use crate::database::*;
+use crate::locale::{channel_language, locale};
+use crate::templates::Values;
+
+// Runs per page of /runs, the most speedrun.com allows
+const PAGE_SIZE: usize = 200;
+
+// speedrun.com can't list users by country, so look for them among the
+// players of recently verified runs, which also finds only active runners
+pub async fn run(ctx: Arc<Context>) {
+    loop {
+        discover(&ctx).await;
+        sleep(get_config().discovery.interval()).await;
+    }
+}
+
+async fn discover(ctx: &Context) {
+    let config = &get_config().discovery;
+    let channel = match config.channel {
+        Some(channel) => channel,
+        None => return,
+    };
+    let db = get_database(ctx).await;
+    let tracked: HashSet<String> = match db.get_runners().await {
+        Ok(runners) => runners.into_iter().filter_map(|r| r.user_id).collect(),
+        Err(why) => {
+            log::error!("Couldn't get runners: {:?}", why);
+            println!("[ERROR] Couldn't get runners");
+            return;
+        }
+    };
+
+    for page in 0..config.pages {
+        let runs = match get_recent_runs(page * PAGE_SIZE, PAGE_SIZE).await {
+            Ok(runs) => runs,
+            Err(why) => {
+                log::error!("Failed to get recent runs: {:?}", why);
+                println!("[ERROR] Failed to get recent runs");
+                return;
+            }
+        };
+        for player in runs.iter().flat_map(|run| run.players.data.iter()) {
+            let (user_id, names) = match (&player.id, &player.names) {
+                (Some(user_id), Some(names)) => (user_id, names),
+                _ => continue,
+            };
+            let country = match matching_country(player, &config.countries) {
+                Some(country) => country,
+                None => continue,
+            };
+            if tracked.contains(user_id) {
+                continue;
+            }
+
+            match db
+                .add_discovered_runner(user_id, &names.international, &country.names.international)
+                .await
+            {
+                Ok(true) => propose(ctx, &db, channel, player, user_id, country).await,
+                Ok(false) => {}
+                Err(why) => {
+                    log::error!("Failed to save found runner: {:?}", why);
+                    println!("[ERROR] Failed to save found runner: {:?}", why);
+                }
+            }
+        }
+        if runs.len() < PAGE_SIZE {
+            break;
+        }
+    }
+}
+
+// The player's country or region if it's one we look for
+fn matching_country<'a>(player: &'a Player, countries: &[String]) -> Option<&'a Country> {
+    let location = player.location.as_ref()?;
+    std::iter::once(&location.country)
+        .chain(location.region.iter())
+        .find(|country| {
+            countries
+                .iter()
+                .any(|code| code.eq_ignore_ascii_case(&country.code))
+        })
+}
+
+// Post a found runner with buttons to start tracking them or ignore them
+async fn propose(
+    ctx: &Context,
+    db: &Database,
+    channel: ChannelId,
+    player: &Player,
+    user_id: &str,
+    country: &Country,
+) {
+    let name = player
+        .names
+        .as_ref()
+        .map_or(user_id, |names| names.international.as_str());
+    let texts = locale(channel_language(ctx, channel).await);
+    let mut embed = CreateEmbed::new()
+        .title(texts.message("discover_title", Values::new().set("name", name)))
+        .field(
+            texts.text("discover_country"),
+            &country.names.international,
+            true,
+        );
+    if let Some(weblink) = &player.weblink {
+        embed = embed.url(weblink);
+    }
+    let buttons = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("discover:approve:{}", user_id))
+            .label(texts.text("discover_track"))
+            .style(ButtonStyle::Success),
+        CreateButton::new(format!("discover:ignore:{}", user_id))
+            .label(texts.text("discover_ignore"))
+            .style(ButtonStyle::Secondary),
+    ])];
+    let builder = CreateMessage::new().embed(embed).components(buttons);
+
+    match channel.send_message(ctx, builder).await {
+        Ok(_) => println!("[INFO] Found runner {}", name),
+        Err(why) => {
+            log::error!("Failed to send message: {:?}", why);
+            println!("[ERROR] Failed to send message: {:?}", why);
+            // Propose them again on the next search
+            if let Err(why) = db.remove_discovered_runner(user_id).await {
+                log::error!("Failed to forget found runner: {:?}", why);
+                println!("[ERROR] Failed to forget found runner: {:?}", why);
+            }
+        }
+    }
+}
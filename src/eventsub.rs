@@ -0,0 +1,538 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::body::HttpBody;
+use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use once_cell::sync::Lazy;
+use serenity::prelude::*;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration, Instant};
+
+use crate::apirequests::*;
+use crate::apitypes::*;
+use crate::config::get_config;
+use crate::database::*;
+use crate::streams;
+
+const STREAM_ONLINE: &str = "stream.online";
+const STREAM_OFFLINE: &str = "stream.offline";
+const CHANNEL_UPDATE: &str = "channel.update";
+
+// Subscription types and their versions kept for every tracked streamer
+const SUBSCRIPTIONS: &[(&str, &str)] = &[
+    (STREAM_ONLINE, "1"),
+    (STREAM_OFFLINE, "1"),
+    (CHANNEL_UPDATE, "2"),
+];
+
+const MESSAGE_ID: &str = "Twitch-Eventsub-Message-Id";
+const MESSAGE_TIMESTAMP: &str = "Twitch-Eventsub-Message-Timestamp";
+const MESSAGE_SIGNATURE: &str = "Twitch-Eventsub-Message-Signature";
+const MESSAGE_TYPE: &str = "Twitch-Eventsub-Message-Type";
+
+// Twitch recommends rejecting notifications older than 10 minutes
+const MAX_MESSAGE_AGE: Duration = Duration::from_secs(600);
+// Notifications are a few KB, anything over this is refused before checking
+// the signature so unsigned requests can't make the bot buffer them
+const MAX_BODY_SIZE: usize = 64 * 1024;
+// How often subscriptions are compared against the streamers table
+const SYNC_INTERVAL: Duration = Duration::from_secs(300);
+// Helix may not list a stream until a short while after stream.online
+const STREAM_LOOKUP_ATTEMPTS: u32 = 6;
+const STREAM_LOOKUP_DELAY: Duration = Duration::from_secs(10);
+
+// Message ids already handled, Twitch may deliver a notification more than once
+static SEEN_MESSAGES: Lazy<Mutex<HashMap<String, Instant>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// A webhook whose signature and age were verified
+#[derive(Debug)]
+pub struct Webhook {
+    pub id: String,
+    // When Twitch sent the message, kept the same on retries
+    pub sent_at: DateTime<Utc>,
+    pub message: WebhookMessage,
+}
+
+#[derive(Debug)]
+pub enum WebhookMessage {
+    Verification(String),
+    Notification(String, EventSubEvent),
+    Revocation(EventSubSubscription),
+    Unknown(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rejection {
+    MissingHeader(&'static str),
+    BadSignature,
+    Expired,
+    Malformed,
+}
+
+// Run the webhook server and keep subscriptions in sync until the server stops
+pub async fn run(ctx: Arc<Context>) -> Result<()> {
+    let listen = get_config().eventsub.listen;
+    let service_ctx = Arc::clone(&ctx);
+    let make_service = make_service_fn(move |_| {
+        let ctx = Arc::clone(&service_ctx);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                handle_request(Arc::clone(&ctx), request)
+            }))
+        }
+    });
+    let server = Server::try_bind(&listen)?.serve(make_service);
+    println!("[INFO] EventSub listening on {}", listen);
+
+    // Twitch verifies new subscriptions right away, so only sync once listening
+    let sync_ctx = Arc::clone(&ctx);
+    let sync_task = tokio::spawn(async move {
+        loop {
+            let db = get_database(&sync_ctx).await;
+            if let Err(why) = sync_subscriptions(&db).await {
+                log::error!("Failed to sync EventSub subscriptions: {:?}", why);
+                println!("[ERROR] Failed to sync EventSub subscriptions: {:?}", why);
+            }
+            sleep(SYNC_INTERVAL).await;
+        }
+    });
+
+    // Only online, offline and update events arrive, so viewers, uptime and
+    // peak viewers of live streams are still fetched like when polling
+    let refresh_ctx = Arc::clone(&ctx);
+    let refresh_task = tokio::spawn(async move {
+        loop {
+            sleep(get_config().polling.streams_interval()).await;
+            streams::refresh(&refresh_ctx).await;
+        }
+    });
+
+    let result = server.await;
+    sync_task.abort();
+    refresh_task.abort();
+    Ok(result?)
+}
+
+// Subscribe to stream and channel events of every tracked streamer and drop
+// subscriptions of streamers that are no longer tracked
+pub async fn sync_subscriptions(db: &Database) -> Result<()> {
+    let callback = get_config().eventsub.callback_url.as_str();
+    let mut wanted: HashSet<(String, String)> = db
+        .get_streamers()
+        .await?
+        .into_iter()
+        .flat_map(|streamer| {
+            SUBSCRIPTIONS
+                .iter()
+                .map(move |(kind, _)| (kind.to_string(), streamer.streamer_id.clone()))
+        })
+        .collect();
+
+    for subscription in get_eventsub_subscriptions().await? {
+        if subscription.transport.callback.as_deref() != Some(callback) {
+            continue;
+        }
+        let key = (
+            subscription.kind.clone(),
+            subscription
+                .condition
+                .broadcaster_user_id
+                .clone()
+                .unwrap_or_default(),
+        );
+        let healthy = matches!(
+            subscription.status.as_str(),
+            "enabled" | "webhook_callback_verification_pending"
+        );
+        if healthy && wanted.remove(&key) {
+            continue;
+        }
+        if let Err(why) = delete_eventsub_subscription(&subscription.id).await {
+            log::error!("Failed to delete subscription {:?}: {:?}", key, why);
+            println!("[ERROR] Failed to delete subscription {:?}", key);
+        }
+    }
+
+    for (kind, user_id) in wanted {
+        let version = SUBSCRIPTIONS
+            .iter()
+            .find(|(known, _)| *known == kind)
+            .map_or("1", |(_, version)| version);
+        match create_eventsub_subscription(&kind, version, &user_id).await {
+            Ok(_) => println!("[INFO] Subscribed to {} for {}", kind, user_id),
+            Err(why) => {
+                log::error!("Failed to subscribe to {} for {}: {:?}", kind, user_id, why);
+                println!("[ERROR] Failed to subscribe to {} for {}", kind, user_id);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn handle_request(
+    ctx: Arc<Context>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::POST {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let (parts, body) = request.into_parts();
+    let declared = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+    if declared.is_some_and(|length| length > MAX_BODY_SIZE) {
+        return Ok(empty_response(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+    let body = match read_body(body).await {
+        Ok(body) => body,
+        Err(status) => return Ok(empty_response(status)),
+    };
+
+    let secret = get_config().eventsub.secret.as_str();
+    let webhook = match parse_webhook(secret, &parts.headers, &body, Utc::now()) {
+        Ok(webhook) => webhook,
+        Err(rejection) => {
+            log::warn!("Rejected EventSub webhook: {:?}", rejection);
+            println!("[WARN] Rejected EventSub webhook: {:?}", rejection);
+            let status = match rejection {
+                Rejection::BadSignature | Rejection::Expired => StatusCode::FORBIDDEN,
+                Rejection::MissingHeader(_) | Rejection::Malformed => StatusCode::BAD_REQUEST,
+            };
+            return Ok(empty_response(status));
+        }
+    };
+
+    match webhook.message {
+        WebhookMessage::Verification(challenge) => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from(challenge))
+            .unwrap()),
+        WebhookMessage::Notification(kind, event) => {
+            if first_delivery(&webhook.id).await {
+                tokio::spawn(handle_event(ctx, kind, event, webhook.sent_at));
+            }
+            Ok(empty_response(StatusCode::NO_CONTENT))
+        }
+        WebhookMessage::Revocation(subscription) => {
+            log::warn!(
+                "EventSub subscription revoked: {} {}",
+                subscription.kind,
+                subscription.status
+            );
+            println!(
+                "[WARN] EventSub subscription revoked: {} {}",
+                subscription.kind, subscription.status
+            );
+            Ok(empty_response(StatusCode::NO_CONTENT))
+        }
+        WebhookMessage::Unknown(kind) => {
+            println!("[INFO] Ignoring EventSub message type {}", kind);
+            Ok(empty_response(StatusCode::NO_CONTENT))
+        }
+    }
+}
+
+// Read a request body of at most MAX_BODY_SIZE, also when it's sent without
+// a Content-Length
+async fn read_body(mut body: Body) -> Result<Vec<u8>, StatusCode> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if bytes.len() + chunk.len() > MAX_BODY_SIZE {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+// Remember a message id, returns false if it was already handled
+async fn first_delivery(id: &str) -> bool {
+    let mut seen = SEEN_MESSAGES.lock().await;
+    seen.retain(|_, received| received.elapsed() < MAX_MESSAGE_AGE);
+    seen.insert(id.to_string(), Instant::now()).is_none()
+}
+
+async fn handle_event(
+    ctx: Arc<Context>,
+    kind: String,
+    event: EventSubEvent,
+    sent_at: DateTime<Utc>,
+) {
+    match kind.as_str() {
+        STREAM_ONLINE => {
+            let user_ids = vec![event.broadcaster_user_id.clone()];
+            for _ in 0..STREAM_LOOKUP_ATTEMPTS {
+                match get_twitch_streams(&user_ids).await {
+                    Ok(streams) => {
+                        if let Some(stream) = streams.first() {
+                            streams::announce(&ctx, stream).await;
+                            return;
+                        }
+                    }
+                    Err(e) => {
+                        log::error!("Failed to get stream {}", event.broadcaster_user_login);
+                        log::error!("{:?}", e);
+                        println!(
+                            "[ERROR] Failed to get stream for {}",
+                            event.broadcaster_user_login
+                        );
+                    }
+                }
+                sleep(STREAM_LOOKUP_DELAY).await;
+            }
+            println!(
+                "[WARN] {} went online but the stream never showed up",
+                event.broadcaster_user_login
+            );
+        }
+        STREAM_OFFLINE => streams::end(&ctx, &event.broadcaster_user_id, Some(sent_at)).await,
+        CHANNEL_UPDATE => {
+            // Title or game changed, which may also let a filtered stream through
+            match get_twitch_streams(std::slice::from_ref(&event.broadcaster_user_id)).await {
+                Ok(streams) => {
+                    if let Some(stream) = streams.first() {
+                        streams::announce(&ctx, stream).await;
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to get stream {}", event.broadcaster_user_login);
+                    log::error!("{:?}", e);
+                    println!(
+                        "[ERROR] Failed to get stream for {}",
+                        event.broadcaster_user_login
+                    );
+                }
+            }
+        }
+        _ => println!("[INFO] Ignoring EventSub event {}", kind),
+    }
+}
+
+// Verify a webhook's HMAC signature and age, then decode its body
+pub fn parse_webhook(
+    secret: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Result<Webhook, Rejection> {
+    let header = |name: &'static str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or(Rejection::MissingHeader(name))
+    };
+    let id = header(MESSAGE_ID)?;
+    let timestamp = header(MESSAGE_TIMESTAMP)?;
+    let signature = header(MESSAGE_SIGNATURE)?;
+    let message_type = header(MESSAGE_TYPE)?;
+
+    if !verify_signature(secret, id, timestamp, body, signature) {
+        return Err(Rejection::BadSignature);
+    }
+
+    let sent = DateTime::parse_from_rfc3339(timestamp)
+        .map_err(|_| Rejection::Malformed)?
+        .with_timezone(&Utc);
+    let age = now.signed_duration_since(sent);
+    if age.to_std().is_ok_and(|age| age > MAX_MESSAGE_AGE) {
+        return Err(Rejection::Expired);
+    }
+
+    let payload: EventSubPayload =
+        serde_json::from_slice(body).map_err(|_| Rejection::Malformed)?;
+    let message = match message_type {
+        "webhook_callback_verification" => {
+            WebhookMessage::Verification(payload.challenge.ok_or(Rejection::Malformed)?)
+        }
+        "notification" => WebhookMessage::Notification(
+            payload.subscription.kind,
+            payload.event.ok_or(Rejection::Malformed)?,
+        ),
+        "revocation" => WebhookMessage::Revocation(payload.subscription),
+        other => WebhookMessage::Unknown(other.to_string()),
+    };
+
+    Ok(Webhook {
+        id: id.to_string(),
+        sent_at: sent,
+        message,
+    })
+}
+
+// Twitch signs the message id, timestamp and raw body with the subscription secret
+pub fn verify_signature(
+    secret: &str,
+    message_id: &str,
+    timestamp: &str,
+    body: &[u8],
+    signature: &str,
+) -> bool {
+    let expected = match signature
+        .strip_prefix("sha256=")
+        .and_then(|hex_digest| hex::decode(hex_digest).ok())
+    {
+        Some(expected) => expected,
+        None => return false,
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(message_id.as_bytes());
+    mac.update(timestamp.as_bytes());
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    const SECRET: &str = "s3cr3t-for-tests";
+    const TIMESTAMP: &str = "2024-05-01T18:30:00.123456789Z";
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-01T18:31:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn sign(id: &str, timestamp: &str, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(id.as_bytes());
+        mac.update(timestamp.as_bytes());
+        mac.update(body.as_bytes());
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn headers(message_type: &str, timestamp: &str, signature: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(MESSAGE_ID, HeaderValue::from_static("message-1"));
+        headers.insert(MESSAGE_TIMESTAMP, HeaderValue::from_str(timestamp).unwrap());
+        headers.insert(MESSAGE_SIGNATURE, HeaderValue::from_str(signature).unwrap());
+        headers.insert(MESSAGE_TYPE, HeaderValue::from_str(message_type).unwrap());
+        headers
+    }
+
+    fn payload(kind: &str, extra: &str) -> String {
+        format!(
+            r#"{{"subscription":{{"id":"sub-1","type":"{}","status":"enabled","condition":{{"broadcaster_user_id":"1337"}},"transport":{{"method":"webhook","callback":"https://example.com/eventsub"}}}}{}}}"#,
+            kind, extra
+        )
+    }
+
+    #[test]
+    fn answers_verification_challenge() {
+        let body = payload(STREAM_ONLINE, r#","challenge":"pogchamp-kappa-360noscope""#);
+        let headers = headers(
+            "webhook_callback_verification",
+            TIMESTAMP,
+            &sign("message-1", TIMESTAMP, &body),
+        );
+        let webhook = parse_webhook(SECRET, &headers, body.as_bytes(), now()).unwrap();
+        assert_eq!(webhook.id, "message-1");
+        match webhook.message {
+            WebhookMessage::Verification(challenge) => {
+                assert_eq!(challenge, "pogchamp-kappa-360noscope")
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn decodes_stream_online_notification() {
+        let body = payload(
+            STREAM_ONLINE,
+            r#","event":{"id":"9001","broadcaster_user_id":"1337","broadcaster_user_login":"cool_user","broadcaster_user_name":"Cool_User","type":"live","started_at":"2024-05-01T18:29:58Z"}"#,
+        );
+        let headers = headers(
+            "notification",
+            TIMESTAMP,
+            &sign("message-1", TIMESTAMP, &body),
+        );
+        match parse_webhook(SECRET, &headers, body.as_bytes(), now())
+            .unwrap()
+            .message
+        {
+            WebhookMessage::Notification(kind, event) => {
+                assert_eq!(kind, STREAM_ONLINE);
+                assert_eq!(event.broadcaster_user_id, "1337");
+                assert_eq!(event.broadcaster_user_login, "cool_user");
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_body() {
+        let body = payload(STREAM_OFFLINE, "");
+        let headers = headers(
+            "notification",
+            TIMESTAMP,
+            &sign("message-1", TIMESTAMP, &body),
+        );
+        let tampered = body.replace("1337", "1338");
+        let rejection = parse_webhook(SECRET, &headers, tampered.as_bytes(), now()).unwrap_err();
+        assert_eq!(rejection, Rejection::BadSignature);
+    }
+
+    #[test]
+    fn rejects_wrong_secret() {
+        let body = payload(STREAM_OFFLINE, "");
+        let headers = headers(
+            "notification",
+            TIMESTAMP,
+            &sign("message-1", TIMESTAMP, &body),
+        );
+        let rejection =
+            parse_webhook("another-secret", &headers, body.as_bytes(), now()).unwrap_err();
+        assert_eq!(rejection, Rejection::BadSignature);
+    }
+
+    #[test]
+    fn rejects_old_messages() {
+        let timestamp = "2024-05-01T18:00:00Z";
+        let body = payload(STREAM_OFFLINE, "");
+        let headers = headers(
+            "notification",
+            timestamp,
+            &sign("message-1", timestamp, &body),
+        );
+        let rejection = parse_webhook(SECRET, &headers, body.as_bytes(), now()).unwrap_err();
+        assert_eq!(rejection, Rejection::Expired);
+    }
+
+    #[test]
+    fn rejects_missing_signature() {
+        let body = payload(STREAM_OFFLINE, "");
+        let mut headers = headers("notification", TIMESTAMP, "sha256=00");
+        headers.remove(MESSAGE_SIGNATURE);
+        let rejection = parse_webhook(SECRET, &headers, body.as_bytes(), now()).unwrap_err();
+        assert_eq!(rejection, Rejection::MissingHeader(MESSAGE_SIGNATURE));
+    }
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_refused() {
+        let body = read_body(Body::from(vec![b'a'; MAX_BODY_SIZE])).await;
+        assert_eq!(body.map(|body| body.len()), Ok(MAX_BODY_SIZE));
+
+        let body = read_body(Body::from(vec![b'a'; MAX_BODY_SIZE + 1])).await;
+        assert_eq!(body, Err(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+}
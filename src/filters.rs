@@ -0,0 +1,194 @@
+use anyhow::{bail, Result};
+use regex::{Regex, RegexBuilder};
+
+use crate::apitypes::TwitchStream;
+use crate::database::StreamFilter;
+
+// Rules deciding which live streams get announced. A streamer's own rules
+// replace the global rules of the same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    // Game must be one of these
+    AllowGame,
+    // Game must not be any of these
+    BlockGame,
+    // Stream must have at least one of these tags
+    RequireTag,
+    // Title must match at least one of these patterns, ignoring case
+    TitleRegex,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 4] = [
+        FilterKind::AllowGame,
+        FilterKind::BlockGame,
+        FilterKind::RequireTag,
+        FilterKind::TitleRegex,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterKind::AllowGame => "allow_game",
+            FilterKind::BlockGame => "block_game",
+            FilterKind::RequireTag => "require_tag",
+            FilterKind::TitleRegex => "title_regex",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<FilterKind> {
+        FilterKind::ALL.iter().copied().find(|k| k.as_str() == kind)
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            FilterKind::AllowGame => "Only announce this game",
+            FilterKind::BlockGame => "Never announce this game",
+            FilterKind::RequireTag => "Require this Twitch tag",
+            FilterKind::TitleRegex => "Require the title to match this regex, ignoring case",
+        }
+    }
+}
+
+// Check a rule before it's saved
+pub fn validate(kind: FilterKind, value: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        bail!("Value must not be empty");
+    }
+    if kind == FilterKind::TitleRegex {
+        title_regex(value)?;
+    }
+    Ok(())
+}
+
+// Check a live stream against the global rules and the rules of its streamer
+pub fn allows(filters: &[StreamFilter], stream: &TwitchStream) -> bool {
+    let game = stream.game_name.to_lowercase();
+    FilterKind::ALL.iter().all(|&kind| {
+        let rules = rules_for(filters, kind, &stream.user_id);
+        if rules.is_empty() {
+            return true;
+        }
+        match kind {
+            FilterKind::AllowGame => rules.iter().any(|rule| rule.to_lowercase() == game),
+            FilterKind::BlockGame => !rules.iter().any(|rule| rule.to_lowercase() == game),
+            FilterKind::RequireTag => rules.iter().any(|rule| {
+                stream
+                    .tags
+                    .iter()
+                    .flatten()
+                    .any(|tag| tag.to_lowercase() == rule.to_lowercase())
+            }),
+            FilterKind::TitleRegex => rules.iter().any(|rule| {
+                title_regex(rule)
+                    .map(|regex| regex.is_match(&stream.title))
+                    .unwrap_or(false)
+            }),
+        }
+    })
+}
+
+fn title_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+fn rules_for<'a>(filters: &'a [StreamFilter], kind: FilterKind, streamer_id: &str) -> Vec<&'a str> {
+    let of_kind = filters.iter().filter(|filter| filter.kind == kind);
+    let own: Vec<&str> = of_kind
+        .clone()
+        .filter(|filter| filter.streamer_id.as_deref() == Some(streamer_id))
+        .map(|filter| filter.value.as_str())
+        .collect();
+    if !own.is_empty() {
+        return own;
+    }
+    of_kind
+        .filter(|filter| filter.streamer_id.is_none())
+        .map(|filter| filter.value.as_str())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(streamer_id: Option<&str>, kind: FilterKind, value: &str) -> StreamFilter {
+        StreamFilter {
+            id: 0,
+            streamer_id: streamer_id.map(String::from),
+            kind,
+            value: value.to_string(),
+        }
+    }
+
+    fn stream(user_id: &str, game: &str, title: &str, tags: &[&str]) -> TwitchStream {
+        TwitchStream {
+            id: String::from("1"),
+            user_id: user_id.to_string(),
+            user_name: String::from("Streamer"),
+            title: title.to_string(),
+            game_name: game.to_string(),
+            thumbnail_url: String::new(),
+            viewer_count: 0,
+            started_at: String::new(),
+            tags: Some(tags.iter().map(|tag| tag.to_string()).collect()),
+        }
+    }
+
+    #[test]
+    fn allows_everything_without_rules() {
+        assert!(allows(&[], &stream("1", "Just Chatting", "hi", &[])));
+    }
+
+    #[test]
+    fn streamer_rules_replace_global_rules() {
+        let filters = [
+            filter(None, FilterKind::AllowGame, "Celeste"),
+            filter(Some("1"), FilterKind::AllowGame, "Super Mario 64"),
+        ];
+        assert!(allows(&filters, &stream("1", "super mario 64", "", &[])));
+        assert!(!allows(&filters, &stream("1", "Celeste", "", &[])));
+        assert!(allows(&filters, &stream("2", "Celeste", "", &[])));
+        assert!(!allows(&filters, &stream("2", "Super Mario 64", "", &[])));
+    }
+
+    #[test]
+    fn blocks_games() {
+        let filters = [filter(None, FilterKind::BlockGame, "Just Chatting")];
+        assert!(!allows(&filters, &stream("1", "Just Chatting", "", &[])));
+        assert!(allows(&filters, &stream("1", "Celeste", "", &[])));
+    }
+
+    #[test]
+    fn requires_one_of_the_tags() {
+        let filters = [
+            filter(None, FilterKind::RequireTag, "Speedrun"),
+            filter(None, FilterKind::RequireTag, "RTA"),
+        ];
+        assert!(allows(
+            &filters,
+            &stream("1", "", "", &["English", "speedrun"])
+        ));
+        assert!(allows(&filters, &stream("1", "", "", &["RTA"])));
+        assert!(!allows(&filters, &stream("1", "", "", &["English"])));
+        let mut untagged = stream("1", "", "", &[]);
+        untagged.tags = None;
+        assert!(!allows(&filters, &untagged));
+    }
+
+    #[test]
+    fn matches_titles_ignoring_case() {
+        let filters = [filter(None, FilterKind::TitleRegex, "!pb")];
+        assert!(allows(&filters, &stream("1", "", "Any% runs !PB", &[])));
+        assert!(!allows(&filters, &stream("1", "", "Any% runs", &[])));
+    }
+
+    #[test]
+    fn matches_titles_with_regexes() {
+        let filters = [filter(None, FilterKind::TitleRegex, r"\[RTA\]|^race\b")];
+        assert!(allows(&filters, &stream("1", "", "[rta] 120 star", &[])));
+        assert!(allows(&filters, &stream("1", "", "Race vs friends", &[])));
+        assert!(!allows(&filters, &stream("1", "", "RTA practice", &[])));
+        assert!(validate(FilterKind::TitleRegex, "[RTA").is_err());
+        assert!(validate(FilterKind::AllowGame, " ").is_err());
+    }
+}
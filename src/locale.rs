@@ -0,0 +1,331 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::NaiveDate;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serenity::{
+    all::{Channel, ChannelId, GuildId},
+    prelude::*,
+};
+use std::collections::HashMap;
+use std::sync::Mutex as StdMutex;
+use tokio::time::Duration;
+use toml::{Table, Value};
+
+use crate::config::get_config;
+use crate::database::{get_database, GuildSettings};
+use crate::templates::{RunTemplates, StreamTemplates, Template, Values};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    Cs,
+    Sk,
+    En,
+}
+
+impl Language {
+    pub const ALL: [Language; 3] = [Language::Cs, Language::Sk, Language::En];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Language::Cs => "cs",
+            Language::Sk => "sk",
+            Language::En => "en",
+        }
+    }
+
+    pub fn parse(code: &str) -> Option<Language> {
+        Language::ALL
+            .iter()
+            .copied()
+            .find(|language| language.code().eq_ignore_ascii_case(code))
+    }
+
+    // Name of the language in itself, shown in the /language choices
+    pub fn native_name(self) -> &'static str {
+        match self {
+            Language::Cs => "Čeština",
+            Language::Sk => "Slovenčina",
+            Language::En => "English",
+        }
+    }
+
+    fn catalogue(self) -> &'static str {
+        match self {
+            Language::Cs => include_str!("../locales/cs.toml"),
+            Language::Sk => include_str!("../locales/sk.toml"),
+            Language::En => include_str!("../locales/en.toml"),
+        }
+    }
+
+    // Run time like 1h 15m 23.500s, with a decimal comma in Czech and Slovak
+    pub fn format_time(self, time: f64) -> String {
+        let duration: Duration = Duration::from_millis((time * 1000.0) as u64);
+        let seconds = (duration.as_millis() as f64) / 1000.0 % 60.0;
+        let minutes = ((duration.as_millis() / 1000 / 60) % 60) as u64;
+        let hours = ((duration.as_millis() / 1000 / 60) / 60) as u64;
+        let is_decimal = seconds.fract() != 0.0;
+        let time_string = if hours != 0 {
+            if is_decimal {
+                format!("{}h {:2}m {:2.3}s", hours, minutes, seconds)
+            } else {
+                format!("{}h {:2}m {:2}s", hours, minutes, seconds)
+            }
+        } else if minutes != 0 {
+            if is_decimal {
+                format!("{}m {:2.3}s", minutes, seconds)
+            } else {
+                format!("{}m {:2}s", minutes, seconds)
+            }
+        } else if is_decimal {
+            format!("{:2.3}s", seconds)
+        } else {
+            format!("{}s", seconds as u64)
+        };
+        match self {
+            Language::Cs | Language::Sk => time_string.replace('.', ","),
+            Language::En => time_string,
+        }
+    }
+
+    // speedrun.com date like 2023-04-29, left as it is if it doesn't parse
+    pub fn format_date(self, date: &str) -> String {
+        let date = match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => return date.to_string(),
+        };
+        match self {
+            Language::Cs | Language::Sk => date.format("%-d. %-m. %Y").to_string(),
+            Language::En => date.format("%B %-d, %Y").to_string(),
+        }
+    }
+
+    // Leaderboard place like 2nd, or 2. in Czech and Slovak
+    pub fn ordinal(self, place: u16) -> String {
+        match self {
+            Language::Cs | Language::Sk => format!("{}.", place),
+            Language::En => {
+                let suffix = match (place % 10, place % 100) {
+                    (_, 11..=13) => "th",
+                    (1, _) => "st",
+                    (2, _) => "nd",
+                    (3, _) => "rd",
+                    _ => "th",
+                };
+                format!("{}{}", place, suffix)
+            }
+        }
+    }
+}
+
+// Announcement templates and replies of one language
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Locale {
+    pub run: RunTemplates,
+    pub stream: StreamTemplates,
+    messages: HashMap<String, Template>,
+}
+
+impl Locale {
+    // Catalogue of a language with the [templates] overrides from the config,
+    // first the ones for every language and then the ones for this language
+    fn load(language: Language, overrides: &Table) -> Result<Locale> {
+        let mut catalogue: Table = toml::from_str(language.catalogue())?;
+        let shared: Table = overrides
+            .iter()
+            .filter(|(key, _)| Language::parse(key).is_none())
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        merge(&mut catalogue, shared);
+        match overrides.get(language.code()) {
+            Some(Value::Table(own)) => merge(&mut catalogue, own.clone()),
+            Some(_) => bail!("templates.{} must be a table", language.code()),
+            None => {}
+        }
+
+        let locale: Locale = Value::Table(catalogue).try_into()?;
+        locale.run.check()?;
+        locale.stream.check()?;
+        Ok(locale)
+    }
+
+    pub fn message(&self, key: &str, values: &Values) -> String {
+        match self.messages.get(key) {
+            Some(template) => template.render(values),
+            None => {
+                log::error!("Missing message {}", key);
+                println!("[ERROR] Missing message {}", key);
+                key.to_string()
+            }
+        }
+    }
+
+    // Message without placeholders
+    pub fn text(&self, key: &str) -> String {
+        self.message(key, &Values::new())
+    }
+}
+
+// Replace values in `base` with the ones in `overrides`, merging nested tables
+fn merge(base: &mut Table, overrides: Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(value)) => merge(base, value),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+// Load every catalogue and make sure they all have the messages of the
+// English one, which catches both gaps in translations and typos in overrides
+pub fn load_all(overrides: &Table) -> Result<HashMap<Language, Locale>> {
+    let english = Locale::load(Language::En, &Table::new())?.messages;
+    let mut locales = HashMap::new();
+    for language in Language::ALL {
+        let locale = Locale::load(language, overrides)
+            .map_err(|why| anyhow!("{} messages: {}", language.code(), why))?;
+        locales.insert(language, locale);
+    }
+
+    for (language, locale) in &locales {
+        if let Some(key) = english
+            .keys()
+            .find(|key| !locale.messages.contains_key(*key))
+        {
+            bail!("{} messages: missing {}", language.code(), key);
+        }
+        if let Some(key) = locale
+            .messages
+            .keys()
+            .find(|key| !english.contains_key(*key))
+        {
+            bail!("{} messages: unknown message {}", language.code(), key);
+        }
+    }
+    Ok(locales)
+}
+
+static LOCALES: Lazy<HashMap<Language, Locale>> =
+    Lazy::new(|| load_all(&get_config().templates).expect("Failed to load messages"));
+
+pub fn locale(language: Language) -> &'static Locale {
+    &LOCALES[&language]
+}
+
+// Language picked for a guild with /language, the configured one otherwise
+pub fn settings_language(settings: &GuildSettings) -> Language {
+    settings.language.unwrap_or(get_config().language)
+}
+
+pub async fn guild_language(ctx: &Context, guild_id: Option<GuildId>) -> Language {
+    let guild_id = match guild_id {
+        Some(guild_id) => guild_id,
+        None => return get_config().language,
+    };
+    let db = get_database(ctx).await;
+    match db.get_guild_settings(guild_id).await {
+        Ok(settings) => settings_language(&settings),
+        Err(why) => {
+            log::error!("Failed to get settings of guild {}: {:?}", guild_id, why);
+            println!("[ERROR] Failed to get settings of guild {}", guild_id);
+            get_config().language
+        }
+    }
+}
+
+// Guild of every channel looked up, they never move between guilds
+static CHANNEL_GUILDS: Lazy<StdMutex<HashMap<ChannelId, GuildId>>> =
+    Lazy::new(|| StdMutex::new(HashMap::new()));
+
+// Language of the guild a channel is in
+pub async fn channel_language(ctx: &Context, channel_id: ChannelId) -> Language {
+    let cached = CHANNEL_GUILDS
+        .lock()
+        .expect("Channel guilds lock poisoned")
+        .get(&channel_id)
+        .copied();
+    let guild_id = match cached {
+        Some(guild_id) => Some(guild_id),
+        None => match channel_id.to_channel(ctx).await {
+            Ok(Channel::Guild(channel)) => {
+                CHANNEL_GUILDS
+                    .lock()
+                    .expect("Channel guilds lock poisoned")
+                    .insert(channel_id, channel.guild_id);
+                Some(channel.guild_id)
+            }
+            Ok(_) => None,
+            Err(why) => {
+                log::error!("Failed to get channel {}: {:?}", channel_id, why);
+                println!("[ERROR] Failed to get channel {}", channel_id);
+                None
+            }
+        },
+    };
+    guild_language(ctx, guild_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catalogues_load_with_the_same_messages() {
+        let locales = load_all(&Table::new()).unwrap();
+        assert_eq!(locales.len(), Language::ALL.len());
+    }
+
+    #[test]
+    fn overrides_apply_to_all_or_one_language() {
+        let overrides: Table = toml::from_str(
+            r#"
+            [run]
+            description = "{time}"
+            [messages]
+            unknown_command = "?"
+            [sk.messages]
+            unknown_command = "??"
+            "#,
+        )
+        .unwrap();
+        let locales = load_all(&overrides).unwrap();
+        let mut values = Values::new();
+        values.set("time", "1m 2s");
+        for language in Language::ALL {
+            let locale = &locales[&language];
+            assert_eq!(locale.run.description.render(&values), "1m 2s");
+            let expected = if language == Language::Sk { "??" } else { "?" };
+            assert_eq!(locale.text("unknown_command"), expected);
+        }
+
+        let typo: Table = toml::from_str("[messages]\nunknown_comand = \"?\"").unwrap();
+        assert!(load_all(&typo).is_err());
+        let placeholder: Table = toml::from_str("[run]\ntitle = \"{viewers}\"").unwrap();
+        assert!(load_all(&placeholder).is_err());
+    }
+
+    #[test]
+    fn formats_times_dates_and_places() {
+        assert_eq!(Language::En.format_time(4523.5), "1h 15m 23.500s");
+        assert_eq!(Language::Cs.format_time(62.25), "1m 2,250s");
+        assert_eq!(Language::Sk.format_time(59.0), "59s");
+
+        assert_eq!(Language::En.format_date("2023-04-09"), "April 9, 2023");
+        assert_eq!(Language::Cs.format_date("2023-04-09"), "9. 4. 2023");
+        assert_eq!(Language::Sk.format_date("unknown"), "unknown");
+
+        let english: Vec<String> = [1, 2, 3, 4, 11, 12, 13, 21, 102, 111]
+            .iter()
+            .map(|place| Language::En.ordinal(*place))
+            .collect();
+        assert_eq!(
+            english,
+            ["1st", "2nd", "3rd", "4th", "11th", "12th", "13th", "21st", "102nd", "111th"]
+        );
+        assert_eq!(Language::Cs.ordinal(3), "3.");
+    }
+}
@@ -1,110 +1,123 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use chrono::{DateTime, Utc};
 use log::LevelFilter;
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
 use serenity::{
-    all::{Colour, CreateEmbed, CreateMessage},
+    all::{
+        Channel, Colour, CreateAllowedMentions, CreateEmbed, CreateEmbedAuthor, CreateMessage,
+        Mentionable,
+    },
     async_trait,
-    model::{channel::Message, gateway::Ready, id::ChannelId},
+    model::{application::Interaction, gateway::Ready},
     prelude::*,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use tokio::task;
-use tokio::time::{sleep, Duration};
+use tokio::time::sleep;
 
 use crate::apirequests::*;
 use crate::apitypes::*;
+use crate::cache::MetadataCache;
 use crate::database::*;
+use crate::locale::{locale, settings_language, Language};
+use crate::templates::Values;
 
+pub mod apiclient;
 pub mod apirequests;
 pub mod apitypes;
+pub mod cache;
+pub mod commands;
 pub mod config;
 pub mod database;
+pub mod discovery;
+pub mod eventsub;
+pub mod filters;
+pub mod locale;
+pub mod streams;
+pub mod templates;
 
 struct Handler;
 
 impl Handler {
+    // Settings from the config go to the guild of the configured channels, which
+    // also gets everything tracked from before guilds had their own
+    async fn adopt_config(&self, ctx: &Context) {
+        let config = config::get_config();
+        let channels = match &config.channels {
+            Some(channels) => channels,
+            None => return,
+        };
+        let guild_id = match channels.runs.to_channel(ctx).await {
+            Ok(Channel::Guild(channel)) => channel.guild_id,
+            Ok(_) => {
+                println!("[WARN] Configured runs channel isn't in a server");
+                return;
+            }
+            Err(why) => {
+                log::error!("Failed to get configured runs channel: {:?}", why);
+                println!("[ERROR] Failed to get configured runs channel: {:?}", why);
+                return;
+            }
+        };
+
+        let mut settings = GuildSettings::new(guild_id);
+        settings.runs_channel = Some(channels.runs);
+        settings.streams_channel = Some(channels.streams);
+        settings.records_channel = config.records.channel;
+        settings.admin_role = config.roles.as_ref().map(|roles| roles.admin);
+        settings.records_role = config.records.role;
+        let db = get_database(ctx).await;
+        if let Err(why) = db.adopt_guild(&settings).await {
+            log::error!("Failed to set up guild {}: {:?}", guild_id, why);
+            println!("[ERROR] Failed to set up guild {}: {:?}", guild_id, why);
+        }
+    }
+
     async fn process_streams(&self, ctx: Arc<Context>) {
-        let mut stream_messages: StreamMessage = HashMap::new();
         loop {
-            // Infinitely loop through all streamers in database
-            let streamers: Result<Vec<Streamer>>;
-            let mut data = ctx.data.write().await;
-            let db = data.get_mut::<Database>().unwrap();
-            streamers = db.get_streamers().await;
+            // Sleep between polling cycles to prevent spamming the API
+            sleep(config::get_config().polling.streams_interval()).await;
+
+            // Infinitely poll a snapshot of all streamers in database at once
+            let db = get_database(&ctx).await;
+            let streamers: Result<Vec<Streamer>> = db.get_streamers().await;
 
             let streamers_vector: Vec<Streamer> = match streamers {
                 Ok(s) => s,
                 Err(_) => {
-                    log::error!("Couldn't get runners");
-                    println!("[ERROR] Couldn't get runners");
+                    log::error!("Couldn't get streamers");
+                    println!("[ERROR] Couldn't get streamers");
                     return;
                 }
             };
 
-            for streamer in streamers_vector {
-                // Sleep to prevent spamming the API
-                sleep(Duration::from_millis(5000)).await;
-                match get_twitch_stream(&streamer.streamer_id).await {
-                    Ok(stream_option) => match stream_option {
-                        Some(stream) => {
-                            if stream_messages.contains_key(&streamer.streamer) {
-                                continue;
-                            }
-                            let title = format!("{}", stream.title);
-                            let description =
-                                format!("{} streamuje: {}", stream.user_name, stream.game_name);
-                            let url = format!("https://www.twitch.tv/{}", stream.user_name);
-                            let thumbnail = stream
-                                .thumbnail_url
-                                .replace("{width}", "1280")
-                                .replace("{height}", "720");
-
-                            let embed = CreateEmbed::new()
-                                .title(title)
-                                .description(description)
-                                .url(url)
-                                .image(thumbnail);
-
-                            let builder = CreateMessage::new().embed(embed);
-
-                            match ChannelId::new(1229888105750724718)
-                                .send_message(&ctx, builder)
-                                .await
-                            {
-                                Ok(message) => {
-                                    stream_messages.insert(streamer.streamer, message.id);
-                                }
-                                Err(why) => {
-                                    log::error!("Failed to send message: {:?}", why);
-                                    println!("[ERROR] Failed to send message: {:?}", why);
-                                }
-                            };
-                        }
-                        None => {
-                            if let Some(&message_id) = stream_messages.get(&streamer.streamer) {
-                                if let Err(why) = ChannelId::new(1229888105750724718)
-                                    .delete_message(&ctx, message_id)
-                                    .await
-                                {
-                                    log::error!("Failed to delete message: {:?}", why);
-                                    println!("[ERROR] Failed to delete message: {:?}", why);
-                                }
-
-                                stream_messages.remove(&streamer.streamer);
-                            }
-                        }
-                    },
+            let streamer_ids: Vec<String> = streamers_vector
+                .iter()
+                .map(|s| s.streamer_id.clone())
+                .collect();
+            let mut live_streams: HashMap<String, TwitchStream> =
+                match get_twitch_streams(&streamer_ids).await {
+                    Ok(streams) => streams
+                        .into_iter()
+                        .map(|stream| (stream.user_id.clone(), stream))
+                        .collect(),
                     Err(e) => {
-                        log::error!("Failed to get stream {}", streamer.streamer);
+                        log::error!("Failed to get streams");
                         log::error!("{:?}", e);
-                        println!("[ERROR] Failed to get stream for {}", streamer.streamer);
+                        println!("[ERROR] Failed to get streams");
                         println!("{:?}", e);
                         continue;
                     }
+                };
+
+            for streamer in streamers_vector {
+                match live_streams.remove(&streamer.streamer_id) {
+                    Some(stream) => streams::announce(&ctx, &stream).await,
+                    None => streams::end(&ctx, &streamer.streamer_id, None).await,
                 }
             }
         }
@@ -112,13 +125,13 @@ impl Handler {
 
     async fn process_runs(&self, ctx: Arc<Context>) {
         loop {
-            // Infinitely loop through all runners in database
-            let runners: Result<Vec<Runner>>;
-            {
-                let mut data = ctx.data.write().await;
-                let db = data.get_mut::<Database>().unwrap();
-                runners = db.get_runners().await;
-            }
+            // Pause between passes, requests themselves are rate limited by the API client
+            sleep(config::get_config().polling.runs_interval()).await;
+
+            // Infinitely loop through a snapshot of all runners in database
+            let db = get_database(&ctx).await;
+            let cache = cache::get_metadata_cache(&ctx).await;
+            let runners: Result<Vec<Runner>> = db.get_runners().await;
             let runners_vector: Vec<Runner> = match runners {
                 Ok(r) => r,
                 Err(_) => {
@@ -127,317 +140,398 @@ impl Handler {
                     return;
                 }
             };
-            for runner in runners_vector {
-                loop {
-                    // Sleep to prevent spamming the API
-                    sleep(Duration::from_millis(10000)).await;
-                    // Get latest run from the API
-                    match get_latest_run(&runner.name).await {
-                        Ok(latest_run) => {
-                            match latest_run {
-                                Some(run) => {
-                                    // Run was found, check if it's new, then get other info
-                                    if run.run.id != runner.last_run {
-                                        // Get game info from the API
-                                        let game: Option<Game> =
-                                            match get_game_data(&run.run.game).await {
-                                                Ok(game_data) => game_data,
-                                                Err(_) => {
-                                                    log::error!(
-                                                        "Failed to get game info {:#?}",
-                                                        run.run.game
-                                                    );
-                                                    println!("[ERROR] Failed to get game info");
-                                                    continue;
-                                                }
-                                            };
-
-                                        // Get category info from the API
-                                        let category: Option<Category> =
-                                            match get_category_data(&run.run.category).await {
-                                                Ok(category_data) => category_data,
-                                                Err(_) => {
-                                                    log::error!(
-                                                        "Failed to get category info {:#?}",
-                                                        run.run.category
-                                                    );
-                                                    println!("[ERROR] Failed to get category info");
-                                                    continue;
-                                                }
-                                            };
-
-                                        // Get level info from the API
-                                        let level: Option<Level> = match &run.run.level {
-                                            None => {
-                                                println!("[INFO] Run has no level");
-                                                None
-                                            }
-                                            Some(level) => match get_level_data(&level).await {
-                                                Ok(level_data) => level_data,
-                                                Err(_) => {
-                                                    log::error!(
-                                                        "Failed to get level info {:#?}",
-                                                        run.run.level
-                                                    );
-                                                    println!("[ERROR] Failed to get level info");
-                                                    continue;
-                                                }
-                                            },
-                                        };
-
-                                        // Get variables from the API
-                                        let variables: Option<String> =
-                                            match get_variables(run.run.values.clone()).await {
-                                                Ok(variables_data) => variables_data,
-                                                Err(_) => {
-                                                    log::error!(
-                                                        "Failed to get variables {:#?}",
-                                                        run.run.values
-                                                    );
-                                                    println!("[ERROR] Failed to get variables");
-                                                    continue;
-                                                }
-                                            };
-
-                                        // Preparing data for Embed
-                                        let game: Game = match game {
-                                            Some(game) => game,
-                                            None => continue,
-                                        };
-                                        let category: String = match category {
-                                            Some(category) => category.name,
-                                            None => continue,
-                                        };
-                                        let level: String = match level {
-                                            Some(level) => level.name,
-                                            None => String::from(""),
-                                        };
-                                        let variables: String = match variables {
-                                            Some(variables) => format!(" ({})", variables),
-                                            None => String::from(""),
-                                        };
-
-                                        // Creating Embed
-                                        let title: String;
-                                        if level == "" {
-                                            title = format!(
-                                                "{} — {}{}",
-                                                game.names.international, category, variables
-                                            );
-                                        } else {
-                                            title = format!(
-                                                "{} — {} {}{}",
-                                                game.names.international,
-                                                level,
-                                                category,
-                                                variables
-                                            );
-                                        }
-                                        let time: String = format_time(run.run.times.primary_t);
-                                        let description = format!(
-                                            "**[{} by {}]({})**",
-                                            time, &runner.name, &run.run.weblink
-                                        );
-                                        let colour: Colour = match &run.place {
-                                            1 => Colour::GOLD,
-                                            2 => Colour::LIGHT_GREY,
-                                            3 => Colour::DARK_ORANGE,
-                                            _ => Colour::RED,
-                                        };
-                                        let embed = CreateEmbed::new()
-                                            .title(title)
-                                            .description(description)
-                                            .color(colour)
-                                            .thumbnail(game.assets.cover_medium.uri)
-                                            .field(
-                                                "Leaderboard rank:",
-                                                &run.place.to_string(),
-                                                false,
-                                            )
-                                            .field("Date played:", &run.run.date, false);
-
-                                        let builder = CreateMessage::new().embed(embed);
-
-                                        if let Err(why) = ChannelId::new(788595458729574400)
-                                            .send_message(&ctx, builder)
-                                            .await
-                                        {
-                                            log::error!("Failed to send message: {:?}", why);
-                                            println!("[ERROR] Failed to send message: {:?}", why);
-                                        };
-
-                                        // Updating runner's last run in the database
-                                        {
-                                            let mut data = ctx.data.write().await;
-                                            let db = data.get_mut::<Database>().unwrap();
-                                            let runner_name = runner.name.clone();
-                                            let run_id = run.run.id.clone();
-                                            match db.update_runner(runner.name, run.run.id).await {
-                                                Ok(_) => println!("[INFO] Updated runner"),
-                                                Err(_) => {
-                                                    log::error!(
-                                                        "Failed to update runner: {:#?} {:#?}",
-                                                        runner_name,
-                                                        run_id,
-                                                    );
-                                                    print!("[ERROR] Failed to update runner");
-                                                }
-                                            };
-                                        }
-                                    }
-                                    break;
-                                }
-                                None => println!("[INFO] Runner has no runs"),
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Failed to get latest run for {}", runner.name);
-                            log::error!("{:?}", e);
-                            println!("[ERROR] Failed to get latest run for {}", runner.name);
-                            println!("{:?}", e);
-                            continue;
-                        }
+            for mut runner in runners_vector {
+                // Runners from before user ids were stored get theirs on their first check
+                let user_id: String = match runner.user_id.clone() {
+                    Some(user_id) => user_id,
+                    None => match self.resolve_runner(&db, &mut runner).await {
+                        Some(user_id) => user_id,
+                        None => continue,
+                    },
+                };
+
+                // Get personal bests from the API
+                let runs: Vec<Run> = match get_personal_bests(&user_id).await {
+                    Ok(runs) => runs,
+                    Err(e) => {
+                        log::error!("Failed to get personal bests for {}", runner.name);
+                        log::error!("{:?}", e);
+                        println!("[ERROR] Failed to get personal bests for {}", runner.name);
+                        println!("{:?}", e);
+                        continue;
                     }
-                    break;
+                };
+                if runs.is_empty() {
+                    println!("[INFO] Runner has no runs");
+                    continue;
+                }
+
+                let announced: HashSet<String> = match db.get_announced_runs(runner.id).await {
+                    Ok(announced) => announced,
+                    Err(why) => {
+                        log::error!("Failed to get announced runs: {:?}", why);
+                        println!("[ERROR] Failed to get announced runs");
+                        continue;
+                    }
+                };
+                let new_runs: Vec<&Run> = if runner.runs_seeded {
+                    let since = DateTime::parse_from_rfc3339(&runner.runs_since)
+                        .ok()
+                        .map(|since| since.with_timezone(&Utc));
+                    new_runs(&runs, &announced, since)
+                } else {
+                    // Runners from before every PB was remembered only know their
+                    // latest run, so remember the rest and post the latest if it's new
+                    let latest = latest_run(&runs).filter(|run| run.run.id != runner.last_run);
+                    let run_ids: Vec<String> = runs
+                        .iter()
+                        .filter(|run| latest.is_none_or(|latest| latest.run.id != run.run.id))
+                        .map(|run| run.run.id.clone())
+                        .collect();
+                    if let Err(why) = db.seed_announced_runs(runner.id, &run_ids).await {
+                        log::error!("Failed to remember runs of {}: {:?}", runner.name, why);
+                        println!("[ERROR] Failed to remember runs of {}", runner.name);
+                        continue;
+                    }
+                    latest.into_iter().collect()
+                };
+
+                for run in new_runs {
+                    // Newer runs would move the cutoff past this one, so retry
+                    // it first on the next pass
+                    if !self.post_run(&ctx, &cache, &runner, run).await {
+                        break;
+                    }
+                    // Remember the run so it isn't posted again
+                    let verified_at = verified_at(run).map_or(String::new(), |at| at.to_rfc3339());
+                    match db
+                        .add_announced_run(runner.id, &run.run.id, &verified_at)
+                        .await
+                    {
+                        Ok(_) => println!("[INFO] Updated runner"),
+                        Err(_) => {
+                            log::error!(
+                                "Failed to update runner: {:#?} {:#?}",
+                                runner.name,
+                                run.run.id,
+                            );
+                            println!("[ERROR] Failed to update runner");
+                        }
+                    };
                 }
             }
         }
     }
-}
 
-#[async_trait]
-impl EventHandler for Handler {
-    async fn message(&self, ctx: Context, msg: Message) {
-        // Add new runner with !srcadd command
-        if msg.content.starts_with("!srcadd ") && msg.channel_id == 788595458729574400 {
-            match &msg.member {
-                Some(member) => {
-                    let found_role: bool = member.roles.iter().any(|&x| x == 467012114725470240);
-                    if found_role {
-                        let mut data = ctx.data.write().await;
-                        let db = data.get_mut::<Database>().unwrap();
-                        let runner: Vec<_> = msg.content.split("!srcadd ").collect();
-                        let latest_run = get_latest_run(runner[1]).await;
-                        let run_id: String = match latest_run {
-                            Ok(latest_run) => match latest_run {
-                                Some(x) => x.run.id,
-                                None => {
-                                    println!("[INFO] Runner has no runs");
-                                    String::from("")
-                                }
-                            },
-                            Err(_) => {
-                                log::error!("Failed to get latest run: {:#?}", runner);
-                                println!("[ERROR] Failed to get latest run");
-                                return;
-                            }
-                        };
-                        let added = db.add_runner(runner[1], &run_id).await;
-                        match added {
-                            Ok(_) => println!("[INFO] Added new runner"),
-                            Err(_) => {
-                                log::error!("Failed to add runner: {:#?} {:#?}", runner[1], run_id);
-                                println!("[ERROR] Failed to add runner");
-                            }
-                        }
+    // Look up the speedrun.com user id of a runner by name and store it along
+    // with the current spelling of the name
+    async fn resolve_runner(&self, db: &Database, runner: &mut Runner) -> Option<String> {
+        let user: User = match get_user_by_name(&runner.name).await {
+            Ok(Some(user)) => user,
+            Ok(None) => {
+                log::error!("Runner {} is not on speedrun.com", runner.name);
+                println!("[ERROR] Runner {} is not on speedrun.com", runner.name);
+                return None;
+            }
+            Err(why) => {
+                log::error!("Failed to look up runner {}: {:?}", runner.name, why);
+                println!("[ERROR] Failed to look up runner {}", runner.name);
+                return None;
+            }
+        };
+        let name = &user.names.international;
+        if let Err(why) = db.set_runner_user(runner.id, &user.id, name).await {
+            log::error!("Failed to save user id of {}: {:?}", runner.name, why);
+            println!("[ERROR] Failed to save user id of {}", runner.name);
+            return None;
+        }
+        println!("[INFO] Stored user id of runner {}", name);
+        runner.name = name.clone();
+        runner.user_id = Some(user.id.clone());
+        Some(user.id)
+    }
+
+    // Follow renames on speedrun.com, which would otherwise only leave the old
+    // name in announcements and commands as runners are checked by user id
+    async fn refresh_names(&self, ctx: Arc<Context>) {
+        let interval = match config::get_config().polling.names_interval() {
+            Some(interval) => interval,
+            None => return,
+        };
+        loop {
+            sleep(interval).await;
+
+            let db = get_database(&ctx).await;
+            let runners: Vec<Runner> = match db.get_runners().await {
+                Ok(runners) => runners,
+                Err(why) => {
+                    log::error!("Couldn't get runners: {:?}", why);
+                    println!("[ERROR] Couldn't get runners");
+                    continue;
+                }
+            };
+            for runner in runners {
+                let user_id = match &runner.user_id {
+                    Some(user_id) => user_id,
+                    None => continue,
+                };
+                let user: User = match get_user(user_id).await {
+                    Ok(Some(user)) => user,
+                    Ok(None) => continue,
+                    Err(why) => {
+                        log::error!("Failed to get user {}: {:?}", user_id, why);
+                        println!("[ERROR] Failed to get user {}", user_id);
+                        continue;
+                    }
+                };
+                let name = &user.names.international;
+                if *name == runner.name {
+                    continue;
+                }
+                match db.set_runner_user(runner.id, user_id, name).await {
+                    Ok(()) => println!("[INFO] Runner {} is now called {}", runner.name, name),
+                    Err(why) => {
+                        log::error!("Failed to rename runner {}: {:?}", runner.name, why);
+                        println!("[ERROR] Failed to rename runner {}", runner.name);
                     }
                 }
-                None => println!("[WARN] User is not in the Guild"),
-            }
-            match msg.delete(&ctx.http).await {
-                Ok(_) => return,
-                Err(_) => println!("[ERROR] Failed to delete message"),
             }
         }
+    }
 
-        if msg.content.starts_with("!streamadd ") && msg.channel_id == 1229888105750724718 {
-            match &msg.member {
-                Some(member) => {
-                    let found_role: bool = member.roles.iter().any(|&x| x == 467012114725470240);
-                    if found_role {
-                        let mut data = ctx.data.write().await;
-                        let db = data.get_mut::<Database>().unwrap();
-                        let streamer: Vec<_> = msg.content.split("!streamadd ").collect();
-                        let twitch_user = get_twitch_user_id(streamer[1]).await;
-                        let streamer_id: String = match twitch_user {
-                            Ok(twitch_user) => match twitch_user {
-                                Some(twitch_user) => twitch_user.id,
-                                None => {
-                                    log::error!(
-                                        "Failed to get Twitch user id for: {:#?}",
-                                        streamer
-                                    );
-                                    println!("[ERROR] Failed to get latest run");
-                                    String::from("")
-                                }
-                            },
-                            Err(_) => {
-                                log::error!("Failed to get Twitch user id for: {:#?}", streamer);
-                                println!("[ERROR] Failed to get latest run");
-                                return;
-                            }
-                        };
-
-                        let added = db.add_streamer(streamer[1], &streamer_id).await;
-                        match added {
-                            Ok(_) => println!("[INFO] Added new streamer"),
-                            Err(_) => {
-                                log::error!(
-                                    "Failed to add streamer: {:#?} {:#?}",
-                                    streamer[1],
-                                    streamer_id
-                                );
-                                println!("[ERROR] Failed to add streamer");
-                            }
-                        }
+    // Post a new PB in every guild tracking the runner, returns false if it
+    // should be tried again later
+    async fn post_run(
+        &self,
+        ctx: &Context,
+        cache: &MetadataCache,
+        runner: &Runner,
+        run: &Run,
+    ) -> bool {
+        // Get game, category, level and variables, embedded in the run when possible
+        let details: RunDetails = match get_run_details(cache, run).await {
+            Ok(details) => details,
+            Err(why) => {
+                log::error!("Failed to get run details {:#?}: {:?}", run.run.id, why);
+                println!("[ERROR] Failed to get run details");
+                return false;
+            }
+        };
+        let db = get_database(ctx).await;
+        let guilds: Vec<GuildSettings> = match db.get_runner_guilds(runner.id).await {
+            Ok(guilds) => guilds,
+            Err(why) => {
+                log::error!("Failed to get guilds of {}: {:?}", runner.name, why);
+                println!("[ERROR] Failed to get guilds of {}", runner.name);
+                return false;
+            }
+        };
+
+        // Get the leaderboard for the previous PB and records, the run is posted
+        // without them on failure
+        let leaderboard: Option<Leaderboard> = match get_leaderboard(&run.run).await {
+            Ok(leaderboard) => Some(leaderboard),
+            Err(why) => {
+                log::error!("Failed to get leaderboard {:#?}: {:?}", run.run.id, why);
+                println!("[ERROR] Failed to get leaderboard");
+                None
+            }
+        };
+        let previous: Option<PreviousPb> = match (&leaderboard, &runner.user_id) {
+            (Some(leaderboard), Some(user_id)) => {
+                match get_previous_pb(run, user_id, leaderboard).await {
+                    Ok(previous) => previous,
+                    Err(why) => {
+                        log::error!("Failed to get previous PB {:#?}: {:?}", run.run.id, why);
+                        println!("[ERROR] Failed to get previous PB");
+                        None
                     }
                 }
-                None => println!("[WARN] User is not in the Guild"),
             }
-            match msg.delete(&ctx.http).await {
-                Ok(_) => return,
-                Err(_) => println!("[ERROR] Failed to delete message"),
+            _ => None,
+        };
+        let countries = &config::get_config().records.countries;
+        let record: Option<Record> = leaderboard
+            .as_ref()
+            .and_then(|leaderboard| find_record(run, leaderboard, countries));
+
+        for settings in guilds {
+            // Records can go to their own channel
+            let mut channel = match settings.runs_channel {
+                Some(channel) => channel,
+                None => continue,
+            };
+            let mut builder = CreateMessage::new();
+            if record.is_some() {
+                channel = settings.records_channel.unwrap_or(channel);
+                if let Some(role) = settings.records_role {
+                    builder = builder
+                        .content(role.mention().to_string())
+                        .allowed_mentions(CreateAllowedMentions::new().roles(vec![role]));
+                }
             }
+            // Stream and Discord account of the runner, posted without them on failure
+            let member: Option<MemberProfile> =
+                match db.get_runner_member(settings.guild_id, runner.id).await {
+                    Ok(member) => member,
+                    Err(why) => {
+                        log::error!("Failed to get member of {}: {:?}", runner.name, why);
+                        println!("[ERROR] Failed to get member of {}", runner.name);
+                        None
+                    }
+                };
+            let language = settings_language(&settings);
+            let embed = run_embed(language, runner, run, &details, &previous, &record, &member);
+
+            if let Err(why) = channel.send_message(ctx, builder.embed(embed)).await {
+                log::error!("Failed to send message: {:?}", why);
+                println!("[ERROR] Failed to send message: {:?}", why);
+            };
+        }
+        true
+    }
+}
+
+// Announcement of a new PB in the given language
+fn run_embed(
+    language: Language,
+    runner: &Runner,
+    run: &Run,
+    details: &RunDetails,
+    previous: &Option<PreviousPb>,
+    record: &Option<Record>,
+    member: &Option<MemberProfile>,
+) -> CreateEmbed {
+    // Preparing data for Embed
+    let game: &Game = &details.game;
+    let players: &str = details.players.as_deref().unwrap_or(&runner.name);
+    let mut values = Values::new();
+    values
+        .set("runner", players)
+        .set("game", &game.names.international)
+        .set("category", &details.category)
+        .set("level", details.level.as_deref().unwrap_or_default())
+        .set(
+            "variables",
+            details.variables.as_deref().unwrap_or_default(),
+        )
+        .set("time", language.format_time(run.run.times.primary_t))
+        .set("rank", language.ordinal(run.place))
+        .set("date", language.format_date(&run.run.date))
+        .set("link", &run.run.weblink);
+    if let Some(previous) = previous {
+        let saved = (previous.time - run.run.times.primary_t).max(0.0);
+        values
+            .set("previous_time", language.format_time(previous.time))
+            .set("time_saved", language.format_time(saved))
+            .set("previous_rank", language.ordinal(previous.rank));
+        if run.place < previous.rank {
+            values.set("places_gained", previous.rank - run.place);
+        }
+    }
+    if let Some(Record::National(country)) = record {
+        values
+            .set("country", &country.names.international)
+            .set("flag", flag(&country.code));
+    }
+    if let Some(member) = member {
+        if let Some(discord_id) = member.discord_id {
+            values.set("discord", discord_id.mention());
+        }
+        if let Some(streamer) = &member.streamer {
+            values.set("stream", format!("https://www.twitch.tv/{}", streamer));
+        }
+        if let Some(youtube) = &member.youtube {
+            values.set("youtube", youtube);
+        }
+    }
+
+    // Creating Embed
+    let templates = &locale(language).run;
+    let colour: Colour = match (record, &run.place) {
+        (Some(Record::National(_)), _) => Colour::BLUE,
+        (_, 1) => Colour::GOLD,
+        (_, 2) => Colour::LIGHT_GREY,
+        (_, 3) => Colour::DARK_ORANGE,
+        _ => Colour::RED,
+    };
+    let mut embed = CreateEmbed::new();
+    if let Some(record) = record {
+        let heading = match record {
+            Record::World => templates.world_record.render(&values),
+            Record::National(_) => templates.national_record.render(&values),
+        };
+        embed = embed.author(CreateEmbedAuthor::new(heading));
+    }
+    let mut embed = embed
+        .title(templates.title.render(&values))
+        .description(templates.description.render(&values))
+        .color(colour)
+        .thumbnail(&game.assets.cover_medium.uri);
+    embed = templates.rank.add_to(embed, &values, false);
+    if previous.is_some() {
+        embed = templates.previous_pb.add_to(embed, &values, true);
+        embed = templates.time_saved.add_to(embed, &values, true);
+        embed = templates.rank_change.add_to(embed, &values, true);
+    }
+    embed = templates.date.add_to(embed, &values, false);
+    templates.links.add_to(embed, &values, false)
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        match interaction {
+            Interaction::Command(command) => commands::run(&ctx, &command).await,
+            Interaction::Autocomplete(command) => commands::autocomplete(&ctx, &command).await,
+            Interaction::Component(component) => commands::component(&ctx, &component).await,
+            _ => {}
         }
     }
 
     async fn ready(&self, ctx: Context, ready: Ready) {
         println!("[INFO] {} is connected!", ready.user.name);
+        commands::register(&ctx).await;
+        Handler.adopt_config(&ctx).await;
         let ctx_arx = Arc::new(ctx);
         let runs_task = task::spawn(Handler.process_runs(Arc::clone(&ctx_arx)));
-        let streams_task = task::spawn(Handler.process_streams(Arc::clone(&ctx_arx)));
+        let names_task = task::spawn(Handler.refresh_names(Arc::clone(&ctx_arx)));
+        let streams_ctx = Arc::clone(&ctx_arx);
+        let streams_task = task::spawn(async move {
+            streams::restore(&streams_ctx).await;
+            if config::get_config().eventsub.enabled {
+                match eventsub::run(Arc::clone(&streams_ctx)).await {
+                    Ok(_) => println!("[WARN] EventSub server stopped, falling back to polling"),
+                    Err(why) => {
+                        log::error!("EventSub failed, falling back to polling: {:?}", why);
+                        println!(
+                            "[ERROR] EventSub failed, falling back to polling: {:?}",
+                            why
+                        );
+                    }
+                }
+            }
+            Handler.process_streams(streams_ctx).await
+        });
+
+        let discovery_ctx = Arc::clone(&ctx_arx);
+        let discovery_task = task::spawn(async move {
+            if config::get_config().discovery.enabled {
+                discovery::run(discovery_ctx).await
+            }
+        });
 
-        let _ = tokio::join!(runs_task, streams_task);
+        let _ = tokio::join!(runs_task, names_task, streams_task, discovery_task);
     }
 }
 
-fn format_time(time: f64) -> String {
-    let duration: Duration = Duration::from_millis((time * 1000.0) as u64);
-    let seconds = (duration.as_millis() as f64) / 1000.0 % 60.0;
-    let minutes = ((duration.as_millis() / 1000 / 60) % 60) as u64;
-    let hours = ((duration.as_millis() / 1000 / 60) / 60) as u64;
-    let is_decimal = !(seconds.fract() == 0.0);
-    let time_string: String;
-    if hours != 0 {
-        if is_decimal {
-            time_string = format!("{}h {:2}m {:2.3}s", hours, minutes, seconds);
-        } else {
-            time_string = format!("{}h {:2}m {:2}s", hours, minutes, seconds);
-        }
-    } else if minutes != 0 {
-        if is_decimal {
-            time_string = format!("{}m {:2.3}s", minutes, seconds);
-        } else {
-            time_string = format!("{}m {:2}s", minutes, seconds);
-        }
-    } else {
-        if is_decimal {
-            time_string = format!("{:2.3}s", seconds);
-        } else {
-            time_string = format!("{}s", seconds as u64);
-        }
-    }
-    time_string
+// Flag emoji of a two letter country code
+fn flag(code: &str) -> String {
+    code.chars()
+        .take(2)
+        .filter(|c| c.is_ascii_alphabetic())
+        .filter_map(|c| char::from_u32(0x1F1E6 + (c.to_ascii_lowercase() as u32 - 'a' as u32)))
+        .collect()
 }
 
 #[tokio::main]
@@ -455,20 +549,36 @@ async fn main() -> Result<()> {
 
     // Discord Token (better as environmental variable)
     let config = config::get_config();
+    if let Err(why) = config.validate() {
+        log::error!("Invalid config: {:?}", why);
+        println!("[ERROR] Invalid config: {:?}", why);
+        return Err(why);
+    }
     let token = config.discord_token.as_str();
 
-    let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+    let intents = GatewayIntents::GUILDS;
 
-    let mut client = Client::builder(&token, intents)
+    let mut client = Client::builder(token, intents)
         .event_handler(Handler)
         .await
         .expect("[ERROR] Error creating client");
     {
-        // Add Database into client's data
+        // Add Database, metadata cache and live stream announcements into client's data
         let mut w = client.data.write().await;
+        w.insert::<streams::LiveStreams>(Arc::new(Mutex::new(HashMap::new())));
         match connect() {
-            Ok(db) => w.insert::<Database>(db),
-            Err(_) => println!("[ERROR] Database failed to connect"),
+            Ok(db) => {
+                let db = Arc::new(db);
+                w.insert::<cache::MetadataCache>(Arc::new(cache::MetadataCache::new(Arc::clone(
+                    &db,
+                ))));
+                w.insert::<Database>(db);
+            }
+            Err(why) => {
+                log::error!("Database failed to connect: {:?}", why);
+                println!("[ERROR] Database failed to connect: {:?}", why);
+                return Err(why);
+            }
         }
     }
     // Start Discord bot
@@ -0,0 +1,493 @@
+use chrono::{DateTime, Utc};
+use serenity::{
+    all::{Colour, CreateEmbed, CreateMessage, EditMessage, GuildId},
+    prelude::*,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::time::Instant;
+
+use crate::apirequests::*;
+use crate::apitypes::*;
+use crate::config::{self, StreamEnded};
+use crate::database::*;
+use crate::filters;
+use crate::locale::{guild_language, locale, settings_language, Language};
+use crate::templates::Values;
+
+// An announced stream and what it looked like when its embed was last edited
+pub struct LiveStream {
+    pub announcement: StreamAnnouncement,
+    pub last_seen: Option<TwitchStream>,
+    pub refreshed_at: Instant,
+}
+
+// Live stream announcements keyed by guild and Twitch user id
+pub type StreamMessage = HashMap<(GuildId, String), LiveStream>;
+
+// Live stream announcements, shared by polling and EventSub
+pub struct LiveStreams;
+
+impl TypeMapKey for LiveStreams {
+    type Value = Arc<Mutex<StreamMessage>>;
+}
+
+pub async fn get_live_streams(ctx: &Context) -> Arc<Mutex<StreamMessage>> {
+    let data = ctx.data.read().await;
+    Arc::clone(
+        data.get::<LiveStreams>()
+            .expect("LiveStreams is not in the TypeMap"),
+    )
+}
+
+// Reload saved announcements, deleting the ones whose stream ended while the
+// bot was down, and announce streams that started in the meantime
+pub async fn restore(ctx: &Context) {
+    let db = get_database(ctx).await;
+    let announcements = match db.get_announcements().await {
+        Ok(announcements) => announcements,
+        Err(why) => {
+            log::error!("Failed to load stream announcements: {:?}", why);
+            println!("[ERROR] Failed to load stream announcements: {:?}", why);
+            return;
+        }
+    };
+    let streamer_ids: Vec<String> = match db.get_streamers().await {
+        Ok(streamers) => streamers.into_iter().map(|s| s.streamer_id).collect(),
+        Err(why) => {
+            log::error!("Couldn't get streamers: {:?}", why);
+            println!("[ERROR] Couldn't get streamers");
+            Vec::new()
+        }
+    };
+
+    // Without knowing what's live, keep every announcement and let the next
+    // check clean them up
+    let live: Option<HashMap<String, TwitchStream>> = match get_twitch_streams(&streamer_ids).await
+    {
+        Ok(streams) => Some(
+            streams
+                .into_iter()
+                .map(|stream| (stream.user_id.clone(), stream))
+                .collect(),
+        ),
+        Err(e) => {
+            log::error!("Failed to get streams");
+            log::error!("{:?}", e);
+            println!("[ERROR] Failed to get streams");
+            None
+        }
+    };
+
+    // Announcements of streams that ended, by Twitch user id
+    let mut ended: HashMap<String, Vec<StreamAnnouncement>> = HashMap::new();
+    {
+        let live_streams = get_live_streams(ctx).await;
+        let mut stream_messages = live_streams.lock().await;
+        for announcement in announcements {
+            let key = (announcement.guild_id, announcement.streamer_id.clone());
+            if stream_messages.contains_key(&key) {
+                continue;
+            }
+            let still_live = match &live {
+                Some(live) => live
+                    .get(&announcement.streamer_id)
+                    .is_some_and(|stream| stream.id == announcement.stream_id),
+                None => true,
+            };
+            if still_live {
+                println!(
+                    "[INFO] Adopted announcement of {}",
+                    announcement.streamer_id
+                );
+                // Nothing was seen yet, so the embed gets refreshed on the next check
+                stream_messages.insert(
+                    key,
+                    LiveStream {
+                        announcement,
+                        last_seen: None,
+                        refreshed_at: Instant::now(),
+                    },
+                );
+            } else {
+                ended
+                    .entry(announcement.streamer_id.clone())
+                    .or_default()
+                    .push(announcement);
+            }
+        }
+    }
+    for announcements in ended.into_values() {
+        finish(ctx, &db, announcements, None).await;
+    }
+
+    if let Some(live) = live {
+        for stream in live.values() {
+            announce(ctx, stream).await;
+        }
+    }
+}
+
+// Announce a stream in every guild tracking the streamer
+pub async fn announce(ctx: &Context, stream: &TwitchStream) {
+    let db = get_database(ctx).await;
+    let guilds: Vec<GuildSettings> = match db.get_streamer_guilds(&stream.user_id).await {
+        Ok(guilds) => guilds,
+        Err(why) => {
+            log::error!("Failed to get guilds of {}: {:?}", stream.user_name, why);
+            println!("[ERROR] Failed to get guilds of {}", stream.user_name);
+            return;
+        }
+    };
+    for settings in guilds {
+        announce_in(ctx, &db, &settings, stream).await;
+    }
+}
+
+// Refresh the announcements of streams that are still live, for EventSub
+// which doesn't report viewer counts. Streams no longer live are ended like
+// when polling, in case their offline notification never arrived
+pub async fn refresh(ctx: &Context) {
+    let streamer_ids: Vec<String> = {
+        let live_streams = get_live_streams(ctx).await;
+        let stream_messages = live_streams.lock().await;
+        let streamer_ids: HashSet<String> = stream_messages
+            .keys()
+            .map(|(_, streamer_id)| streamer_id.clone())
+            .collect();
+        streamer_ids.into_iter().collect()
+    };
+    if streamer_ids.is_empty() {
+        return;
+    }
+    match get_twitch_streams(&streamer_ids).await {
+        Ok(streams) => {
+            for stream in &streams {
+                announce(ctx, stream).await;
+            }
+            for streamer_id in &streamer_ids {
+                if !streams.iter().any(|stream| stream.user_id == *streamer_id) {
+                    end(ctx, streamer_id, None).await;
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to refresh streams");
+            log::error!("{:?}", e);
+            println!("[ERROR] Failed to refresh streams");
+        }
+    }
+}
+
+// Post an announcement for a new stream that passes the guild's filters, or
+// edit the existing one when the title or game changed or its viewers and
+// uptime are due for a refresh
+async fn announce_in(
+    ctx: &Context,
+    db: &Database,
+    settings: &GuildSettings,
+    stream: &TwitchStream,
+) {
+    let live_streams = get_live_streams(ctx).await;
+    let mut stream_messages = live_streams.lock().await;
+    let language = settings_language(settings);
+
+    let key = (settings.guild_id, stream.user_id.clone());
+    if let Some(live_stream) = stream_messages.get_mut(&key) {
+        // Saved every time for the end of streams that go offline while the bot is down
+        track_stats(&mut live_stream.announcement, stream);
+        live_stream.announcement.last_seen_at = Utc::now().to_rfc3339();
+        if let Err(why) = db.add_announcement(&live_stream.announcement).await {
+            log::error!("Failed to save announcement: {:?}", why);
+            println!("[ERROR] Failed to save announcement: {:?}", why);
+        }
+
+        let changed = match &live_stream.last_seen {
+            Some(last_seen) => {
+                last_seen.title != stream.title || last_seen.game_name != stream.game_name
+            }
+            None => true,
+        };
+        let refresh = config::get_config().polling.stream_refresh_interval();
+        let stale = refresh.is_some_and(|refresh| live_stream.refreshed_at.elapsed() >= refresh);
+        if !changed && !stale {
+            return;
+        }
+
+        let announcement = &live_stream.announcement;
+        let builder = EditMessage::new().embed(stream_embed(stream, language));
+        match announcement
+            .channel_id
+            .edit_message(ctx, announcement.message_id, builder)
+            .await
+        {
+            Ok(_) => {
+                live_stream.last_seen = Some(stream.clone());
+                live_stream.refreshed_at = Instant::now();
+            }
+            Err(why) => {
+                log::error!("Failed to edit message: {:?}", why);
+                println!("[ERROR] Failed to edit message: {:?}", why);
+            }
+        }
+        return;
+    }
+
+    let channel_id = match settings.streams_channel {
+        Some(channel_id) => channel_id,
+        None => return,
+    };
+    match db
+        .get_stream_filters(settings.guild_id, Some(&stream.user_id))
+        .await
+    {
+        Ok(filters) => {
+            if !filters::allows(&filters, stream) {
+                return;
+            }
+        }
+        Err(why) => {
+            log::error!("Failed to get stream filters: {:?}", why);
+            println!("[ERROR] Failed to get stream filters: {:?}", why);
+        }
+    }
+
+    let builder = CreateMessage::new().embed(stream_embed(stream, language));
+    match channel_id.send_message(ctx, builder).await {
+        Ok(message) => {
+            let announcement = StreamAnnouncement {
+                guild_id: settings.guild_id,
+                streamer_id: stream.user_id.clone(),
+                stream_id: stream.id.clone(),
+                channel_id,
+                message_id: message.id,
+                user_name: stream.user_name.clone(),
+                title: stream.title.clone(),
+                started_at: stream.started_at.clone(),
+                games: vec![stream.game_name.clone()],
+                peak_viewers: stream.viewer_count,
+                last_seen_at: Utc::now().to_rfc3339(),
+            };
+            if let Err(why) = db.add_announcement(&announcement).await {
+                log::error!("Failed to save announcement: {:?}", why);
+                println!("[ERROR] Failed to save announcement: {:?}", why);
+            }
+            stream_messages.insert(
+                key,
+                LiveStream {
+                    announcement,
+                    last_seen: Some(stream.clone()),
+                    refreshed_at: Instant::now(),
+                },
+            );
+        }
+        Err(why) => {
+            log::error!("Failed to send message: {:?}", why);
+            println!("[ERROR] Failed to send message: {:?}", why);
+        }
+    };
+}
+
+// Update title, games played and peak viewers
+fn track_stats(announcement: &mut StreamAnnouncement, stream: &TwitchStream) {
+    announcement.title = stream.title.clone();
+    if !stream.game_name.is_empty() && !announcement.games.contains(&stream.game_name) {
+        announcement.games.push(stream.game_name.clone());
+    }
+    announcement.peak_viewers = announcement.peak_viewers.max(stream.viewer_count);
+}
+
+fn stream_embed(stream: &TwitchStream, language: Language) -> CreateEmbed {
+    let templates = &locale(language).stream;
+    let url = format!("https://www.twitch.tv/{}", stream.user_name);
+    let mut values = Values::new();
+    values
+        .set("streamer", &stream.user_name)
+        .set("title", &stream.title)
+        .set("game", &stream.game_name)
+        .set("viewers", stream.viewer_count)
+        .set("uptime", format_uptime(&stream.started_at, Utc::now()))
+        .set("link", &url);
+    // Discord caches images by URL, so bust it to show the current frame
+    let thumbnail = format!(
+        "{}?t={}",
+        stream
+            .thumbnail_url
+            .replace("{width}", "1280")
+            .replace("{height}", "720"),
+        Utc::now().timestamp()
+    );
+
+    let embed = CreateEmbed::new()
+        .title(templates.title.render(&values))
+        .description(templates.description.render(&values))
+        .url(url)
+        .image(thumbnail);
+    let embed = templates.viewers.add_to(embed, &values, true);
+    templates.uptime.add_to(embed, &values, true)
+}
+
+// Summary of a finished stream replacing its announcement
+fn ended_embed(
+    announcement: &StreamAnnouncement,
+    vod: Option<&TwitchVideo>,
+    ended_at: DateTime<Utc>,
+    language: Language,
+) -> CreateEmbed {
+    let templates = &locale(language).stream;
+    let link = format!("https://www.twitch.tv/{}", announcement.user_name);
+    let url = match vod {
+        Some(vod) => vod.url.clone(),
+        None => link.clone(),
+    };
+    let mut values = Values::new();
+    values
+        .set("streamer", &announcement.user_name)
+        .set("title", &announcement.title)
+        .set(
+            "game",
+            announcement.games.last().cloned().unwrap_or_default(),
+        )
+        .set("games", announcement.games.join(", "))
+        .set("uptime", format_uptime(&announcement.started_at, ended_at))
+        .set("peak_viewers", announcement.peak_viewers)
+        .set("link", link)
+        .set(
+            "vod_url",
+            vod.map(|vod| vod.url.as_str()).unwrap_or_default(),
+        );
+
+    let embed = CreateEmbed::new()
+        .title(templates.title.render(&values))
+        .description(templates.ended_description.render(&values))
+        .url(url)
+        .color(Colour::DARK_GREY);
+    let embed = templates.duration.add_to(embed, &values, true);
+    let embed = templates.peak_viewers.add_to(embed, &values, true);
+    let embed = templates.games.add_to(embed, &values, false);
+    templates.vod.add_to(embed, &values, false)
+}
+
+// Time from the start of the stream until now, or its duration once it ended
+fn format_uptime(started_at: &str, until: DateTime<Utc>) -> String {
+    let started_at = match DateTime::parse_from_rfc3339(started_at) {
+        Ok(started_at) => started_at.with_timezone(&Utc),
+        Err(_) => return String::from("?"),
+    };
+    let minutes = until.signed_duration_since(started_at).num_minutes().max(0);
+    if minutes >= 60 {
+        format!("{}h {}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+// Delete the announcements of a stream that went offline or turn them into
+// summaries, depending on the config. Without the time it went offline, it
+// ended when it was last seen live
+pub async fn end(ctx: &Context, user_id: &str, ended_at: Option<DateTime<Utc>>) {
+    let announcements: Vec<StreamAnnouncement> = {
+        let live_streams = get_live_streams(ctx).await;
+        let mut stream_messages = live_streams.lock().await;
+        let keys: Vec<(GuildId, String)> = stream_messages
+            .keys()
+            .filter(|(_, streamer_id)| streamer_id == user_id)
+            .cloned()
+            .collect();
+        keys.iter()
+            .filter_map(|key| stream_messages.remove(key))
+            .map(|live_stream| live_stream.announcement)
+            .collect()
+    };
+    let db = get_database(ctx).await;
+    finish(ctx, &db, announcements, ended_at).await;
+}
+
+// Finish the announcements of one stream in every guild it was posted in
+async fn finish(
+    ctx: &Context,
+    db: &Database,
+    announcements: Vec<StreamAnnouncement>,
+    ended_at: Option<DateTime<Utc>>,
+) {
+    let first = match announcements.first() {
+        Some(first) => first,
+        None => return,
+    };
+    let ended_at: DateTime<Utc> = ended_at
+        .or_else(|| {
+            announcements
+                .iter()
+                .filter_map(|announcement| {
+                    DateTime::parse_from_rfc3339(&announcement.last_seen_at).ok()
+                })
+                .map(|last_seen_at| last_seen_at.with_timezone(&Utc))
+                .max()
+        })
+        .unwrap_or_else(Utc::now);
+    let config = &config::get_config().streams;
+    let vod: Option<TwitchVideo> = if config.ended == StreamEnded::Summary || config.record_sessions
+    {
+        match get_twitch_vod(&first.streamer_id, &first.stream_id).await {
+            Ok(vod) => vod,
+            Err(e) => {
+                log::error!("Failed to get VOD for {}", first.user_name);
+                log::error!("{:?}", e);
+                println!("[ERROR] Failed to get VOD for {}", first.user_name);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if config.record_sessions {
+        let vod_url = vod.as_ref().map(|vod| vod.url.as_str());
+        if let Err(why) = db.add_session(first, &ended_at.to_rfc3339(), vod_url).await {
+            log::error!("Failed to record stream session: {:?}", why);
+            println!("[ERROR] Failed to record stream session: {:?}", why);
+        }
+    }
+
+    for announcement in &announcements {
+        match config.ended {
+            StreamEnded::Delete => delete_announcement(ctx, db, announcement).await,
+            StreamEnded::Summary => {
+                let language = guild_language(ctx, Some(announcement.guild_id)).await;
+                let embed = ended_embed(announcement, vod.as_ref(), ended_at, language);
+                let builder = EditMessage::new().embed(embed);
+                if let Err(why) = announcement
+                    .channel_id
+                    .edit_message(ctx, announcement.message_id, builder)
+                    .await
+                {
+                    log::error!("Failed to edit message: {:?}", why);
+                    println!("[ERROR] Failed to edit message: {:?}", why);
+                }
+                forget_announcement(db, announcement).await;
+            }
+        }
+    }
+}
+
+async fn delete_announcement(ctx: &Context, db: &Database, announcement: &StreamAnnouncement) {
+    if let Err(why) = announcement
+        .channel_id
+        .delete_message(ctx, announcement.message_id)
+        .await
+    {
+        log::error!("Failed to delete message: {:?}", why);
+        println!("[ERROR] Failed to delete message: {:?}", why);
+    }
+    forget_announcement(db, announcement).await;
+}
+
+async fn forget_announcement(db: &Database, announcement: &StreamAnnouncement) {
+    if let Err(why) = db
+        .remove_announcement(announcement.guild_id, &announcement.streamer_id)
+        .await
+    {
+        log::error!("Failed to forget announcement: {:?}", why);
+        println!("[ERROR] Failed to forget announcement: {:?}", why);
+    }
+}
@@ -0,0 +1,358 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use serenity::all::CreateEmbed;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+// Placeholders available in run announcements
+pub const RUN_PLACEHOLDERS: &[&str] = &[
+    "runner",
+    "game",
+    "category",
+    "level",
+    "variables",
+    "time",
+    "rank",
+    "date",
+    "link",
+    "previous_time",
+    "time_saved",
+    "previous_rank",
+    "places_gained",
+    "country",
+    "flag",
+    "discord",
+    "stream",
+    "youtube",
+];
+
+// Placeholders available in live stream announcements and their summaries
+pub const STREAM_PLACEHOLDERS: &[&str] = &[
+    "streamer",
+    "title",
+    "game",
+    "viewers",
+    "uptime",
+    "peak_viewers",
+    "games",
+    "link",
+    "vod_url",
+];
+
+// Text with {placeholders}. {#name}...{/name} is only shown when the value is
+// not empty and {^name}...{/name} only when it is. {{ and }} are literal braces.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Value(String),
+    // Placeholder, whether it must be empty to show the parts, and the parts
+    Section(String, bool, Vec<Part>),
+}
+
+// Placeholder of a section being parsed and whether it's inverted, None for
+// the template itself
+type OpenSection = Option<(String, bool)>;
+
+impl TryFrom<String> for Template {
+    type Error = anyhow::Error;
+
+    fn try_from(source: String) -> Result<Self> {
+        Template::parse(&source)
+    }
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template> {
+        // Parts of every open section, the innermost last
+        let mut stack: Vec<(OpenSection, Vec<Part>)> = vec![(None, Vec::new())];
+        let mut text = String::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => bail!("Unexpected }} in \"{}\", use }}}} for a brace", source),
+                '{' => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => bail!("Unclosed {{ in \"{}\"", source),
+                        }
+                    }
+                    let parts = &mut stack.last_mut().expect("Template stack is empty").1;
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+
+                    if let Some(name) = tag.strip_prefix('#') {
+                        stack.push((Some((check_name(name)?, false)), Vec::new()));
+                    } else if let Some(name) = tag.strip_prefix('^') {
+                        stack.push((Some((check_name(name)?, true)), Vec::new()));
+                    } else if let Some(name) = tag.strip_prefix('/') {
+                        let (section, parts) = stack.pop().expect("Template stack is empty");
+                        match section {
+                            Some((open, inverted)) if open == name.trim() => stack
+                                .last_mut()
+                                .expect("Template stack is empty")
+                                .1
+                                .push(Part::Section(open, inverted, parts)),
+                            _ => bail!("Unexpected {{/{}}} in \"{}\"", name, source),
+                        }
+                    } else {
+                        parts.push(Part::Value(check_name(&tag)?));
+                    }
+                }
+                c => text.push(c),
+            }
+        }
+
+        let (section, mut parts) = stack.pop().expect("Template stack is empty");
+        if let Some((name, _)) = section {
+            bail!("Section {} is never closed in \"{}\"", name, source);
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts })
+    }
+
+    // Make sure the template only uses the given placeholders
+    pub fn check(&self, placeholders: &[&str]) -> Result<()> {
+        fn check_parts(parts: &[Part], placeholders: &[&str]) -> Result<()> {
+            for part in parts {
+                match part {
+                    Part::Text(_) => {}
+                    Part::Value(name) | Part::Section(name, _, _)
+                        if !placeholders.contains(&name.as_str()) =>
+                    {
+                        bail!(
+                            "Unknown placeholder {{{}}}, available are {}",
+                            name,
+                            placeholders.join(", ")
+                        )
+                    }
+                    Part::Value(_) => {}
+                    Part::Section(_, _, parts) => check_parts(parts, placeholders)?,
+                }
+            }
+            Ok(())
+        }
+        check_parts(&self.parts, placeholders)
+    }
+
+    pub fn render(&self, values: &Values) -> String {
+        fn render_parts(parts: &[Part], values: &Values, output: &mut String) {
+            for part in parts {
+                match part {
+                    Part::Text(text) => output.push_str(text),
+                    Part::Value(name) => output.push_str(values.get(name)),
+                    Part::Section(name, inverted, parts) => {
+                        if values.get(name).is_empty() == *inverted {
+                            render_parts(parts, values, output);
+                        }
+                    }
+                }
+            }
+        }
+        let mut output = String::new();
+        render_parts(&self.parts, values, &mut output);
+        output
+    }
+}
+
+fn check_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        bail!("Invalid placeholder name \"{}\"", name);
+    }
+    Ok(name.to_string())
+}
+
+// Values of placeholders, missing ones render as empty
+#[derive(Debug, Default)]
+pub struct Values(HashMap<&'static str, String>);
+
+impl Values {
+    pub fn new() -> Self {
+        Values::default()
+    }
+
+    pub fn set(&mut self, name: &'static str, value: impl ToString) -> &mut Self {
+        self.0.insert(name, value.to_string());
+        self
+    }
+
+    fn get(&self, name: &str) -> &str {
+        self.0.get(name).map_or("", |value| value.as_str())
+    }
+}
+
+// Embed field, left out when its name or value renders empty
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FieldTemplate {
+    pub name: Template,
+    pub value: Template,
+}
+
+impl FieldTemplate {
+    pub fn add_to(&self, embed: CreateEmbed, values: &Values, inline: bool) -> CreateEmbed {
+        let name = self.name.render(values);
+        let value = self.value.render(values);
+        if name.trim().is_empty() || value.trim().is_empty() {
+            return embed;
+        }
+        embed.field(name, value, inline)
+    }
+
+    fn check(&self, placeholders: &[&str]) -> Result<()> {
+        self.name.check(placeholders)?;
+        self.value.check(placeholders)
+    }
+}
+
+// Layout of run announcements, [run] in the catalogues
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RunTemplates {
+    pub title: Template,
+    pub description: Template,
+    pub world_record: Template,
+    pub national_record: Template,
+    pub rank: FieldTemplate,
+    pub previous_pb: FieldTemplate,
+    pub time_saved: FieldTemplate,
+    pub rank_change: FieldTemplate,
+    pub date: FieldTemplate,
+    pub links: FieldTemplate,
+}
+
+impl RunTemplates {
+    // Check every template only uses placeholders it will be given
+    pub fn check(&self) -> Result<()> {
+        for (name, template) in [
+            ("run.title", &self.title),
+            ("run.description", &self.description),
+            ("run.world_record", &self.world_record),
+            ("run.national_record", &self.national_record),
+        ] {
+            check(name, template.check(RUN_PLACEHOLDERS))?;
+        }
+        for (name, field) in [
+            ("run.rank", &self.rank),
+            ("run.previous_pb", &self.previous_pb),
+            ("run.time_saved", &self.time_saved),
+            ("run.rank_change", &self.rank_change),
+            ("run.date", &self.date),
+            ("run.links", &self.links),
+        ] {
+            check(name, field.check(RUN_PLACEHOLDERS))?;
+        }
+        Ok(())
+    }
+}
+
+// Layout of live stream announcements and their summaries, [stream] in the catalogues
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct StreamTemplates {
+    pub title: Template,
+    pub description: Template,
+    pub viewers: FieldTemplate,
+    pub uptime: FieldTemplate,
+    // Summary of an ended stream
+    pub ended_description: Template,
+    pub duration: FieldTemplate,
+    pub peak_viewers: FieldTemplate,
+    pub games: FieldTemplate,
+    pub vod: FieldTemplate,
+}
+
+impl StreamTemplates {
+    pub fn check(&self) -> Result<()> {
+        for (name, template) in [
+            ("stream.title", &self.title),
+            ("stream.description", &self.description),
+            ("stream.ended_description", &self.ended_description),
+        ] {
+            check(name, template.check(STREAM_PLACEHOLDERS))?;
+        }
+        for (name, field) in [
+            ("stream.viewers", &self.viewers),
+            ("stream.uptime", &self.uptime),
+            ("stream.duration", &self.duration),
+            ("stream.peak_viewers", &self.peak_viewers),
+            ("stream.games", &self.games),
+            ("stream.vod", &self.vod),
+        ] {
+            check(name, field.check(STREAM_PLACEHOLDERS))?;
+        }
+        Ok(())
+    }
+}
+
+fn check(name: &str, result: Result<()>) -> Result<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(why) => bail!("{}: {}", name, why),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, values: &Values) -> String {
+        Template::parse(source).unwrap().render(values)
+    }
+
+    #[test]
+    fn renders_placeholders_and_braces() {
+        let mut values = Values::new();
+        values.set("runner", "Runner").set("time", "1m 2s");
+        assert_eq!(
+            render("{{{runner}}} ran {time}{missing}", &values),
+            "{Runner} ran 1m 2s"
+        );
+    }
+
+    #[test]
+    fn renders_sections_by_emptiness() {
+        let source = "{#level}{level} {/level}{category}{^level} (full game){/level}";
+        let mut values = Values::new();
+        values.set("category", "Any%");
+        assert_eq!(render(source, &values), "Any% (full game)");
+        values.set("level", "Stage 1");
+        assert_eq!(render(source, &values), "Stage 1 Any%");
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        assert!(Template::parse("{runner").is_err());
+        assert!(Template::parse("runner}").is_err());
+        assert!(Template::parse("{#level}{level}").is_err());
+        assert!(Template::parse("{#level}{/category}").is_err());
+        assert!(Template::parse("{run ner}").is_err());
+    }
+
+    #[test]
+    fn checks_placeholders() {
+        let template = Template::parse("{#viewers}{title}{/viewers}").unwrap();
+        assert!(template.check(STREAM_PLACEHOLDERS).is_ok());
+        assert!(template.check(RUN_PLACEHOLDERS).is_err());
+    }
+}